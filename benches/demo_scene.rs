@@ -0,0 +1,47 @@
+// Renders the demo scene with the release build and reports how long it takes. The
+// math variants are compared by running it once per feature set:
+//
+//   cargo bench
+//   cargo bench --features simd
+//   cargo bench --features f32
+//   cargo bench --features f32,simd
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const SAMPLES_PER_PIXEL: &str = "16";
+const RUNS: usize = 3;
+
+fn main() {
+    let precision = if cfg!(feature = "f32") { "f32" } else { "f64" };
+    let math = if cfg!(feature = "simd") { "simd" } else { "scalar" };
+    let output = std::env::temp_dir().join(format!("demo_scene_{}_{}.ppm", precision, math));
+
+    let mut times: Vec<Duration> = vec![];
+    let mut rays = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let result = Command::new(env!("CARGO_BIN_EXE_RustTracer"))
+            .args(["--output", output.to_str().unwrap(), "--spp", SAMPLES_PER_PIXEL, "--stats", "--no-progress"])
+            .stderr(Stdio::null())
+            .output()
+            .expect("failed to run the renderer");
+        times.push(start.elapsed());
+        assert!(result.status.success(), "render failed");
+        rays = count_rays(&String::from_utf8_lossy(&result.stdout));
+    }
+    times.sort();
+
+    let best = times[0].as_secs_f64();
+    println!(
+        "demo scene, {} {}: best {:.3}s, median {:.3}s of {} runs at {} spp, {:.2} Mrays/s",
+        precision, math, best, times[RUNS / 2].as_secs_f64(), RUNS, SAMPLES_PER_PIXEL, rays as f64 / best / 1e6,
+    );
+}
+
+// Primary and secondary rays from the --stats report.
+fn count_rays(stats: &str) -> u64 {
+    stats.lines()
+        .filter(|line| line.trim_start().starts_with("primary rays") || line.trim_start().starts_with("secondary rays"))
+        .filter_map(|line| line.split_whitespace().last()?.parse::<u64>().ok())
+        .sum()
+}
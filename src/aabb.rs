@@ -0,0 +1,120 @@
+use crate::float::Float;
+use crate::ray::Ray;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+use crate::simd;
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::ORIGIN + 0.5 * (self.min.to_vec() + self.max.to_vec())
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn corners(&self) -> [Point3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z), Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z), Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z), Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z), Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let size = self.size();
+        if size.x > size.y && size.x > size.z {
+            0
+        } else if size.y > size.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Slab test, returns the parametric interval the ray spends inside the box.
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    pub fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float) -> Option<(Float, Float)> {
+        self.slab_test(ray, ray_t_min, ray_t_max)
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    pub fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float) -> Option<(Float, Float)> {
+        simd::slab_test(self.min, self.max, ray, ray_t_min, ray_t_max)
+    }
+
+    // The scalar slab test, SIMD builds keep it for the tests to check against.
+    #[cfg(any(not(all(feature = "simd", target_arch = "x86_64")), test))]
+    fn slab_test(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float) -> Option<(Float, Float)> {
+        let mut t0 = ray_t_min;
+        let mut t1 = ray_t_max;
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let mut near = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let mut far = (self.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+#[cfg(all(test, feature = "simd", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::sampler::Pcg32;
+
+    #[test]
+    fn simd_hit_matches_scalar_slab_test() {
+        let mut rng = Pcg32::new(1);
+        let mut coordinate = |scale: Float| {
+            // a few exact zeros and box planes so rays run parallel to and inside slabs
+            match rng.next_u32() % 8 {
+                0 => 0.0,
+                1 => 1.0,
+                _ => (rng.next_float() - 0.5) * scale,
+            }
+        };
+        for _ in 0..20_000 {
+            let bounds = Aabb::new(
+                Point3::new(coordinate(4.0), coordinate(4.0), coordinate(4.0)),
+                Point3::new(coordinate(4.0), coordinate(4.0), coordinate(4.0)),
+            );
+            let origin = Point3::new(coordinate(8.0), coordinate(8.0), coordinate(8.0));
+            let direction = Vec3::new(coordinate(2.0), coordinate(2.0), coordinate(2.0));
+            let ray = Ray::new(origin, direction, 0.0);
+            let t = coordinate(20.0).abs();
+            let t_max = if t > 0.0 { t } else { Float::INFINITY };
+            let bits = |hit: Option<(Float, Float)>| hit.map(|(t0, t1)| (t0.to_bits(), t1.to_bits()));
+            assert_eq!(bits(bounds.hit(&ray, 0.001, t_max)), bits(bounds.slab_test(&ray, 0.001, t_max)), "{:?} {:?}", bounds, ray);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::ops::{Add, Mul, Sub};
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::color::Color;
+use crate::vec3::Vec3;
+
+// How a track moves from a key to the next one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // cubic curve through the out handle of the key and the in handle of the next one
+    Bezier,
+    // smooth curve through the neighbouring keys, no handles needed
+    CatmullRom,
+}
+
+pub trait Animatable: Copy + PartialEq + Add<Output = Self> + Sub<Output = Self> + Mul<Float, Output = Self> {}
+
+impl Animatable for Float {}
+
+impl Animatable for Vec3 {}
+
+impl Animatable for Color {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Key<T> {
+    pub time: Float,
+    pub value: T,
+    pub interpolation: Interpolation,
+    pub in_handle: T,
+    pub out_handle: T,
+}
+
+// Value changing over time in seconds, holding the first and last key outside of
+// their range.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new() -> Track<T> {
+        Track { keys: vec![] }
+    }
+
+    pub fn key(self, time: Float, value: T, interpolation: Interpolation) -> Track<T> {
+        self.insert(Key { time, value, interpolation, in_handle: value, out_handle: value })
+    }
+
+    // Handles are absolute values the curve is pulled towards before and after the key.
+    pub fn bezier_key(self, time: Float, value: T, in_handle: T, out_handle: T) -> Track<T> {
+        self.insert(Key { time, value, interpolation: Interpolation::Bezier, in_handle, out_handle })
+    }
+
+    fn insert(mut self, key: Key<T>) -> Track<T> {
+        let index = self.keys.partition_point(|k| k.time <= key.time);
+        self.keys.insert(index, key);
+        self
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.keys.iter().all(|k| {
+            let first = self.keys[0].value;
+            k.value == first && k.in_handle == first && k.out_handle == first
+        })
+    }
+
+    pub fn times(&self) -> impl Iterator<Item = Float> + '_ {
+        self.keys.iter().map(|k| k.time)
+    }
+
+    pub fn sample(&self, time: Float) -> T {
+        let keys = &self.keys;
+        let next = keys.partition_point(|k| k.time <= time);
+        if next == 0 || next == keys.len() {
+            return keys[next.min(keys.len() - 1)].value;
+        }
+        let (a, b) = (&keys[next - 1], &keys[next]);
+        let s = (time - a.time) / (b.time - a.time);
+        match a.interpolation {
+            Interpolation::Linear => a.value + (b.value - a.value) * s,
+            Interpolation::Bezier => {
+                let r = 1.0 - s;
+                a.value * (r * r * r) + a.out_handle * (3.0 * r * r * s) + b.in_handle * (3.0 * r * s * s) + b.value * (s * s * s)
+            }
+            Interpolation::CatmullRom => {
+                // missing neighbours at the ends are mirrored so the curve keeps going straight
+                let p1 = a.value;
+                let p2 = b.value;
+                let p0 = if next >= 2 { keys[next - 2].value } else { p1 + (p1 - p2) };
+                let p3 = if next + 1 < keys.len() { keys[next + 1].value } else { p2 + (p2 - p1) };
+                (p1 * 2.0
+                    + (p2 - p0) * s
+                    + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * (s * s)
+                    + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * (s * s * s)) * 0.5
+            }
+        }
+    }
+}
+
+// Camera moves for flythroughs and turntables, tracks that are not set leave the
+// camera alone.
+#[derive(Debug, Default)]
+pub struct CameraAnimation {
+    // offset from the origin, points cannot be added up the way the curves do
+    pub position: Option<Track<Vec3>>,
+    pub forward: Option<Track<Vec3>>,
+}
+
+// Overrides material parameters of whatever the wrapped object hits.
+pub struct AnimatedMaterial {
+    object: Box<dyn Hittable>,
+    albedo: Option<Track<Color>>,
+    parameter: Option<Track<Float>>,
+}
+
+impl AnimatedMaterial {
+    pub fn new(object: Box<dyn Hittable>) -> AnimatedMaterial {
+        AnimatedMaterial { object, albedo: None, parameter: None }
+    }
+
+    pub fn with_albedo(mut self, albedo: Track<Color>) -> AnimatedMaterial {
+        self.albedo = Some(albedo);
+        self
+    }
+
+    // Fuzziness of metals, refraction index of dielectrics.
+    pub fn with_parameter(mut self, parameter: Track<Float>) -> AnimatedMaterial {
+        self.parameter = Some(parameter);
+        self
+    }
+}
+
+impl Hittable for AnimatedMaterial {
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool {
+        if !self.object.hit(ray, ray_t_min, ray_t_max, hit_record) {
+            return false;
+        }
+        if let Some(mut material) = hit_record.material {
+            if let Some(track) = &self.albedo {
+                material = material.with_albedo(track.sample(ray.time));
+            }
+            if let Some(track) = &self.parameter {
+                material = material.with_parameter(track.sample(ray.time));
+            }
+            hit_record.material = Some(material);
+        }
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.object.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_passes_through_keys(track: &Track<Float>, keys: &[(Float, Float)]) {
+        for &(time, value) in keys {
+            assert!((track.sample(time) - value).abs() < 1e-5, "{} at {}", track.sample(time), time);
+        }
+        // the ends are held
+        let (first, last) = (keys[0], keys[keys.len() - 1]);
+        assert_eq!(track.sample(first.0 - 1.0), first.1);
+        assert_eq!(track.sample(last.0 + 10.0), last.1);
+    }
+
+    #[test]
+    fn linear_track() {
+        let track = Track::new().key(2.0, 4.0, Interpolation::Linear).key(0.0, 0.0, Interpolation::Linear);
+        assert_passes_through_keys(&track, &[(0.0, 0.0), (2.0, 4.0)]);
+        assert_eq!(track.sample(1.0), 2.0);
+    }
+
+    #[test]
+    fn bezier_track() {
+        let track = Track::new().bezier_key(0.0, 0.0, 0.0, 4.0).bezier_key(1.0, 0.0, 4.0, 0.0);
+        assert_passes_through_keys(&track, &[(0.0, 0.0), (1.0, 0.0)]);
+        // both handles pull three eighths of the way
+        assert!((track.sample(0.5) - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn catmull_rom_track() {
+        let mut track = Track::new();
+        for time in 0..4 {
+            let time = time as Float;
+            track = track.key(time, time * time, Interpolation::CatmullRom);
+        }
+        assert_passes_through_keys(&track, &[(0.0, 0.0), (1.0, 1.0), (2.0, 4.0), (3.0, 9.0)]);
+        // evenly spaced keys on a parabola are followed exactly between the inner ones
+        assert!((track.sample(1.5) - 2.25).abs() < 1e-5);
+    }
+}
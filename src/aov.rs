@@ -0,0 +1,121 @@
+use std::fs;
+use std::io;
+use crate::film::Features;
+use crate::float::{to_f32, Float};
+
+// Arbitrary output variables, extra render passes recorded next to the beauty image.
+// The light path passes add up to the beauty image: emission covers what the camera
+// sees directly (emitters and the sky), the diffuse passes split the light reflected
+// by a diffuse first hit into one bounce and the rest, and specular holds everything
+// seen through a mirror or glass first hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    Depth,
+    Normal,
+    Albedo,
+    ObjectId,
+    MaterialId,
+    DiffuseDirect,
+    DiffuseIndirect,
+    Specular,
+    Emission,
+}
+
+pub const ALL_AOVS: [Aov; 9] = [
+    Aov::Depth,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::DiffuseDirect,
+    Aov::DiffuseIndirect,
+    Aov::Specular,
+    Aov::Emission,
+];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DiffuseDirect => "diffuse_direct",
+            Aov::DiffuseIndirect => "diffuse_indirect",
+            Aov::Specular => "specular",
+            Aov::Emission => "emission",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Aov> {
+        ALL_AOVS.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            Aov::Depth | Aov::ObjectId | Aov::MaterialId => 1,
+            _ => 3,
+        }
+    }
+
+    // IDs can't be averaged, those passes keep the value of the first sample.
+    fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+
+    fn value(&self, features: &Features) -> [Float; 3] {
+        let color = match self {
+            Aov::Depth => return [features.depth, 0.0, 0.0],
+            // ids are hashes, keep the low 24 bits f32 holds exactly
+            Aov::ObjectId => return [(features.object_id & 0xffffff) as Float, 0.0, 0.0],
+            Aov::MaterialId => return [(features.material_id & 0xffffff) as Float, 0.0, 0.0],
+            Aov::Normal => return [features.normal.x, features.normal.y, features.normal.z],
+            Aov::Albedo => features.albedo,
+            Aov::DiffuseDirect => features.diffuse_direct,
+            Aov::DiffuseIndirect => features.diffuse_indirect,
+            Aov::Specular => features.specular,
+            Aov::Emission => features.emission,
+        };
+        color.to_array()
+    }
+}
+
+// Per pixel sums of one pass, stored bottom row first like the film.
+#[derive(Debug)]
+pub struct AovBuffer {
+    pub aov: Aov,
+    pub data: Vec<f32>,
+}
+
+impl AovBuffer {
+    pub fn new(aov: Aov, pixel_count: usize) -> AovBuffer {
+        AovBuffer { aov, data: vec![0.0; pixel_count * aov.channels()] }
+    }
+
+    pub fn add(&mut self, pixel: usize, features: &Features, sample_count: u32) {
+        let channels = self.aov.channels();
+        let value = self.aov.value(features);
+        for (c, v) in value.iter().take(channels).enumerate() {
+            let slot = &mut self.data[pixel * channels + c];
+            if !self.aov.is_id() {
+                *slot += to_f32(*v);
+            } else if sample_count == 1 {
+                *slot = to_f32(*v);
+            }
+        }
+    }
+
+    // Averaged pass values as a portable float map.
+    pub fn write_pfm(&self, path: &str, width: u32, height: u32, sample_counts: &[u32]) -> io::Result<()> {
+        let channels = self.aov.channels();
+        let mut contents = format!("{}\n{} {}\n-1.0\n", if channels == 3 { "PF" } else { "Pf" }, width, height).into_bytes();
+        for (pixel, values) in self.data.chunks(channels).enumerate() {
+            let scale = if self.aov.is_id() { 1.0 } else { 1.0 / sample_counts[pixel].max(1) as f32 };
+            for v in values {
+                contents.extend_from_slice(&(v * scale).to_le_bytes());
+            }
+        }
+        fs::write(path, contents)
+    }
+}
@@ -0,0 +1,529 @@
+use crate::float::Float;
+use crate::float::consts::PI;
+use crate::color::Color;
+use crate::film::{Features, SplatFilm};
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator::{sky_color, Integrator};
+use crate::libs::sample_unit_vector;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats;
+use crate::vec3::{Normal3, Point3, Vec3};
+
+// The camera as light paths see it, a pinhole they can be connected to.
+#[derive(Clone, Copy, Debug)]
+pub struct PinholeCamera {
+    eye: Point3,
+    // unit view direction through the image centre
+    look: Vec3,
+    // outer corner of pixel (0, 0) and the steps to the next pixel
+    corner: Point3,
+    pixel_delta_u: Vec3,
+    pixel_delta_v: Vec3,
+    width: u32,
+    height: u32,
+    // from the eye to the image plane
+    distance: Float,
+    // of the image plane moved to unit distance
+    area: Float,
+}
+
+impl PinholeCamera {
+    pub fn new(eye: Point3, pixel00: Point3, pixel_delta_u: Vec3, pixel_delta_v: Vec3, width: u32, height: u32) -> PinholeCamera {
+        let corner = pixel00 - 0.5 * (pixel_delta_u + pixel_delta_v);
+        let center = corner + 0.5 * (width as Float * pixel_delta_u + height as Float * pixel_delta_v);
+        let distance = (center - eye).length();
+        let area = width as Float * pixel_delta_u.length() * height as Float * pixel_delta_v.length() / (distance * distance);
+        PinholeCamera { eye, look: (center - eye) / distance, corner, pixel_delta_u, pixel_delta_v, width, height, distance, area }
+    }
+
+    // Pixel the ray from the eye along `direction` goes through, with the cosine
+    // between the ray and the view direction.
+    fn raster(&self, direction: Vec3) -> Option<(u32, u32, Float)> {
+        let direction = direction.normalize();
+        let cos = direction.dot(&self.look);
+        if cos <= 0.0 {
+            return None;
+        }
+        let offset = self.eye + direction * (self.distance / cos) - self.corner;
+        let x = offset.dot(&self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+        let y = offset.dot(&self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+        if !(0.0..self.width as Float).contains(&x) || !(0.0..self.height as Float).contains(&y) {
+            return None;
+        }
+        Some((x as u32, y as u32, cos))
+    }
+
+    // Solid angle density of camera rays along `direction`.
+    fn pdf_direction(&self, direction: Vec3) -> Float {
+        self.raster(direction).map_or(0.0, |(_, _, cos)| 1.0 / (self.area * cos * cos * cos))
+    }
+
+    // Importance of a ray leaving the eye at `cos` to the view direction, spread so
+    // that the whole image adds up to one.
+    fn importance(&self, cos: Float) -> Float {
+        1.0 / (self.area * cos * cos * cos * cos)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+    // scattering inside a volume
+    Medium,
+    // a camera path that left the scene
+    Sky,
+}
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    point: Point3,
+    // faces the side the path arrived from, points out of lights and along the view
+    // direction at the camera
+    normal: Normal3,
+    // unit direction back to the previous vertex
+    wo: Vec3,
+    material: Option<Material>,
+    front_face: bool,
+    // radiance leaving lights and the sky
+    emission: Color,
+    // path contribution up to here over the density of sampling it
+    beta: Color,
+    // scatters in a single direction, so it cannot be connected to
+    delta: bool,
+    // area densities of sampling this vertex from its neighbour towards the camera
+    // end and from the one towards the light end
+    pdf_fwd: Float,
+    pdf_rev: Float,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, point: Point3, normal: Normal3, beta: Color) -> Vertex {
+        Vertex {
+            kind,
+            point,
+            normal,
+            wo: Vec3::new(0.0, 0.0, 0.0),
+            material: None,
+            front_face: true,
+            emission: Color::BLACK,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn on_surface(&self) -> bool {
+        matches!(self.kind, VertexKind::Surface | VertexKind::Light)
+    }
+
+    fn connectible(&self) -> bool {
+        match self.kind {
+            VertexKind::Camera | VertexKind::Light => true,
+            VertexKind::Surface | VertexKind::Medium => !self.delta && self.material.is_some_and(|m| !m.is_specular()),
+            VertexKind::Sky => false,
+        }
+    }
+
+    // Radiance emitted from here towards `towards`.
+    fn le(&self, towards: Point3) -> Color {
+        let zero = Color::BLACK;
+        match self.kind {
+            VertexKind::Sky => self.emission,
+            VertexKind::Light if self.normal.dot(&(towards - self.point)) > 0.0 => self.emission,
+            VertexKind::Surface if !self.front_face => zero,
+            VertexKind::Surface | VertexKind::Medium => self.material.map_or(zero, |m| m.emitted()),
+            _ => zero,
+        }
+    }
+
+    // Scattering towards `next` of light arriving from the previous vertex.
+    fn f(&self, next: &Vertex) -> Color {
+        let wi = (next.point - self.point).normalize();
+        match self.material {
+            Some(material) => material.eval(self.normal, self.wo, wi),
+            None => Color::BLACK,
+        }
+    }
+
+    // Turns a solid angle density at this vertex into an area density at `next`.
+    fn convert_density(&self, pdf: Float, next: &Vertex) -> Float {
+        if next.kind == VertexKind::Sky {
+            return pdf;
+        }
+        let w = next.point - self.point;
+        let inverse_distance_squared = 1.0 / w.length_squared();
+        let mut pdf = pdf * inverse_distance_squared;
+        if next.on_surface() {
+            pdf *= next.normal.dot(&(w * inverse_distance_squared.sqrt())).abs();
+        }
+        pdf
+    }
+}
+
+// Bidirectional path tracer. Every sample traces a camera subpath and a light
+// subpath and connects each prefix of one to each prefix of the other, weighting
+// the strategies with the balance heuristic. Connections straight to the eye land
+// on arbitrary pixels and are splatted. Only lights registered with the scene are
+// sampled, the sky and emissive volumes are found by camera paths alone.
+pub struct BdptIntegrator {
+    pub max_bounces: u32,
+    // None for cameras light paths cannot hit, a lens or a panoramic projection
+    camera: Option<PinholeCamera>,
+    splats: SplatFilm,
+}
+
+impl BdptIntegrator {
+    pub fn new(max_bounces: u32, camera: Option<PinholeCamera>) -> BdptIntegrator {
+        let (width, height) = camera.map_or((0, 0), |c| (c.width, c.height));
+        BdptIntegrator { max_bounces, camera, splats: SplatFilm::new(width, height) }
+    }
+
+    fn camera_subpath(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features, path: &mut Vec<Vertex>) {
+        let direction = ray.direction.normalize();
+        let look = self.camera.map_or(direction, |c| c.look);
+        path.push(Vertex::new(VertexKind::Camera, ray.origin, Normal3::from_vec(look), Color::WHITE));
+        // other cameras never take part in a connection, any density does for them
+        let pdf = self.camera.map_or(1.0, |c| c.pdf_direction(direction));
+        self.random_walk(world, *ray, Color::WHITE, pdf, self.max_bounces as usize + 1, true, sampler, features, path);
+    }
+
+    fn light_subpath(&self, time: Float, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features, path: &mut Vec<Vertex>) {
+        if world.lights.is_empty() || self.max_bounces == 0 {
+            return;
+        }
+        let count = world.lights.len();
+        let light = world.lights[((sampler.get_1d() * count as Float) as usize).min(count - 1)];
+        let (point, normal) = light.sample_point(sampler.get_2d());
+        // cosine weighted, the way a diffuse emitter sends out its light
+        let mut direction = normal + sample_unit_vector(sampler.get_2d());
+        if direction.length_squared() < 1e-12 {
+            direction = normal.to_vec();
+        }
+        let direction = direction.normalize();
+        let pdf_position = 1.0 / (count as Float * light.area());
+        let pdf_direction = normal.dot(&direction) / PI;
+
+        let mut vertex = Vertex::new(VertexKind::Light, point, normal, light.emission / pdf_position);
+        vertex.emission = light.emission;
+        vertex.pdf_fwd = pdf_position;
+        path.push(vertex);
+        let beta = light.emission * normal.dot(&direction) / (pdf_position * pdf_direction);
+        let ray = Ray::new(point, direction, time);
+        self.random_walk(world, ray, beta, pdf_direction, self.max_bounces as usize, false, sampler, features, path);
+    }
+
+    // Extends `path` up to `max_vertices` vertices, starting with `ray` sampled
+    // with solid angle density `pdf` from its last vertex.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(&self, world: &HittableList, mut ray: Ray, mut beta: Color, pdf: Float, max_vertices: usize, from_camera: bool, sampler: &mut dyn Sampler, features: &mut Features, path: &mut Vec<Vertex>) {
+        let mut pdf_fwd = pdf;
+        while path.len() < max_vertices {
+            let mut hit = HitRecord::empty();
+            let first = from_camera && path.len() == 1;
+            let rays = features.rays + 1;
+            if !world.hit(&ray, 0.001, Float::INFINITY, &mut hit) {
+                if from_camera {
+                    let sky = sky_color(&ray);
+                    if first {
+                        // the sky is its own albedo, as for the path tracer
+                        *features = Features { albedo: sky, emission: sky, ..Features::default() };
+                    }
+                    let direction = ray.direction.normalize();
+                    let mut vertex = Vertex::new(VertexKind::Sky, ray.origin + direction, Normal3::from_vec(-direction), beta);
+                    vertex.emission = sky;
+                    vertex.pdf_fwd = pdf_fwd;
+                    path.push(vertex);
+                }
+                features.rays = rays;
+                break;
+            }
+            if first {
+                *features = Features::first_hit(&ray, &hit);
+            }
+            features.rays = rays;
+            let Some(material) = hit.material else {
+                break;
+            };
+
+            let kind = match material {
+                Material::Isotropic { .. } => VertexKind::Medium,
+                _ => VertexKind::Surface,
+            };
+            let wo = -ray.direction.normalize();
+            let mut vertex = Vertex::new(kind, hit.point, hit.normal, beta);
+            vertex.wo = wo;
+            vertex.material = Some(material);
+            vertex.front_face = hit.front_face;
+            vertex.pdf_fwd = path[path.len() - 1].convert_density(pdf_fwd, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let mut scattered = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, 0.0), ray.time);
+            let mut attenuation = Color::BLACK;
+            if !material.scatter(&ray, &hit, &mut attenuation, &mut scattered, sampler) {
+                break;
+            }
+            let wi = scattered.direction.normalize();
+            let last = path.len() - 1;
+            let pdf_rev = if material.is_specular() {
+                path[last].delta = true;
+                pdf_fwd = 0.0;
+                0.0
+            } else {
+                pdf_fwd = material.pdf(hit.normal, wo, wi);
+                material.pdf(hit.normal, wi, wo)
+            };
+            beta = beta * attenuation;
+            path[last - 1].pdf_rev = path[last].convert_density(pdf_rev, &path[last - 1]);
+            ray = scattered;
+        }
+    }
+
+    // Contribution of the path made of the first `s` light and first `t` camera
+    // vertices, and the pixel it lands on when it was connected to the eye.
+    #[allow(clippy::too_many_arguments)]
+    fn connect(&self, world: &HittableList, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize, time: Float, sampler: &mut dyn Sampler) -> (Color, Option<(u32, u32)>) {
+        let zero = Color::BLACK;
+        let pt = camera_path[t - 1];
+        if pt.kind == VertexKind::Sky && s > 0 {
+            return (zero, None);
+        }
+        let mut sampled = None;
+        let mut raster = None;
+        let radiance = if s == 0 {
+            pt.beta * pt.le(camera_path[t - 2].point)
+        } else if t == 1 {
+            let qs = light_path[s - 1];
+            let Some(camera) = self.camera.filter(|_| qs.connectible()) else {
+                return (zero, None);
+            };
+            let to_eye = camera.eye - qs.point;
+            let Some((i, j, cos)) = camera.raster(-to_eye) else {
+                return (zero, None);
+            };
+            // a pinhole has unit lens area, the density of picking the eye is the
+            // inverse of the solid angle it covers
+            let pdf = to_eye.length_squared() / cos;
+            let mut vertex = Vertex::new(VertexKind::Camera, camera.eye, Normal3::from_vec(camera.look), Color::gray(camera.importance(cos) / pdf));
+            vertex.wo = -to_eye.normalize();
+            let mut radiance = qs.beta * qs.f(&vertex) * vertex.beta;
+            if qs.on_surface() {
+                radiance = radiance * qs.normal.dot(&to_eye.normalize()).abs();
+            }
+            if radiance.near_zero() || !self.visible(world, qs.point, camera.eye, time) {
+                return (zero, None);
+            }
+            sampled = Some(vertex);
+            raster = Some((i, j));
+            radiance
+        } else if s == 1 {
+            if !pt.connectible() || world.lights.is_empty() {
+                return (zero, None);
+            }
+            let count = world.lights.len();
+            let light = world.lights[((sampler.get_1d() * count as Float) as usize).min(count - 1)];
+            let (point, normal) = light.sample_point(sampler.get_2d());
+            let to_light = point - pt.point;
+            let distance_squared = to_light.length_squared();
+            let wi = to_light / distance_squared.sqrt();
+            let cos_light = -normal.dot(&wi);
+            if cos_light <= 0.0 {
+                return (zero, None);
+            }
+            // uniform over the light's area, as a solid angle density
+            let pdf = distance_squared / (cos_light * light.area()) / count as Float;
+            let mut vertex = Vertex::new(VertexKind::Light, point, normal, light.emission / pdf);
+            vertex.emission = light.emission;
+            vertex.pdf_fwd = 1.0 / (count as Float * light.area());
+            let mut radiance = pt.beta * pt.f(&vertex) * vertex.beta;
+            if pt.on_surface() {
+                radiance = radiance * pt.normal.dot(&wi).abs();
+            }
+            if radiance.near_zero() || !self.visible(world, pt.point, point, time) {
+                return (zero, None);
+            }
+            sampled = Some(vertex);
+            radiance
+        } else {
+            let qs = light_path[s - 1];
+            if !qs.connectible() || !pt.connectible() {
+                return (zero, None);
+            }
+            let mut radiance = qs.beta * qs.f(&pt) * pt.f(&qs) * pt.beta;
+            if radiance.near_zero() {
+                return (zero, None);
+            }
+            let d = pt.point - qs.point;
+            let distance_squared = d.length_squared();
+            let w = d / distance_squared.sqrt();
+            let mut g = 1.0 / distance_squared;
+            if qs.on_surface() {
+                g *= qs.normal.dot(&w).abs();
+            }
+            if pt.on_surface() {
+                g *= pt.normal.dot(&w).abs();
+            }
+            if !self.visible(world, qs.point, pt.point, time) {
+                return (zero, None);
+            }
+            radiance = radiance * g;
+            radiance
+        };
+        if radiance.near_zero() {
+            return (zero, None);
+        }
+        (radiance * self.mis_weight(world, light_path, camera_path, sampled, s, t), raster)
+    }
+
+    fn visible(&self, world: &HittableList, from: Point3, to: Point3, time: Float) -> bool {
+        stats::record(|s| s.shadow_rays += 1);
+        let d = to - from;
+        let distance = d.length();
+        let mut hit = HitRecord::empty();
+        !world.hit(&Ray::new(from, d / distance, time), 0.001, distance - 0.001, &mut hit)
+    }
+
+    // Area density with which `vertex`, reached from `previous`, samples `next`.
+    fn pdf(&self, world: &HittableList, vertex: &Vertex, previous: Option<&Vertex>, next: &Vertex) -> Float {
+        let direction = (next.point - vertex.point).normalize();
+        let pdf = match vertex.kind {
+            VertexKind::Light => return self.pdf_light(world, vertex, next),
+            VertexKind::Camera => self.camera.map_or(0.0, |c| c.pdf_direction(direction)),
+            _ => match (vertex.material, previous) {
+                (Some(material), Some(previous)) => material.pdf(vertex.normal, (previous.point - vertex.point).normalize(), direction),
+                _ => 0.0,
+            },
+        };
+        vertex.convert_density(pdf, next)
+    }
+
+    // Area density with which a light path leaving the light at `vertex` reaches `next`.
+    fn pdf_light(&self, world: &HittableList, vertex: &Vertex, next: &Vertex) -> Float {
+        if self.pdf_light_origin(world, vertex) == 0.0 {
+            return 0.0;
+        }
+        let w = next.point - vertex.point;
+        let inverse_distance_squared = 1.0 / w.length_squared();
+        let w = w * inverse_distance_squared.sqrt();
+        let mut pdf = vertex.normal.dot(&w).max(0.0) / PI * inverse_distance_squared;
+        if next.on_surface() {
+            pdf *= next.normal.dot(&w).abs();
+        }
+        pdf
+    }
+
+    // Area density of a light path starting at `vertex`, zero away from the lights.
+    fn pdf_light_origin(&self, world: &HittableList, vertex: &Vertex) -> Float {
+        let count = world.lights.len() as Float;
+        world.lights.iter()
+            .find(|light| ((vertex.point - light.center).length() - light.radius).abs() < 1e-6 * light.radius.max(1.0))
+            .map_or(0.0, |light| 1.0 / (count * light.area()))
+    }
+
+    // Balance heuristic weight of connecting after `s` light and `t` camera
+    // vertices, against every other way of sampling the same path.
+    fn mis_weight(&self, world: &HittableList, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<Vertex>, s: usize, t: usize) -> Float {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let mut light = light_path[..s].to_vec();
+        let mut camera = camera_path[..t].to_vec();
+        match sampled {
+            Some(vertex) if s == 1 => light[0] = vertex,
+            Some(vertex) => camera[0] = vertex,
+            None => {}
+        }
+        if s == 0 && (camera[t - 1].kind == VertexKind::Sky || self.pdf_light_origin(world, &camera[t - 1]) == 0.0) {
+            // nothing else finds the sky or emitters that are not lights
+            return 1.0;
+        }
+
+        // the densities across the connection run the other way now
+        let pt = camera[t - 1];
+        let pt_minus = (t > 1).then(|| camera[t - 2]);
+        let qs = (s > 0).then(|| light[s - 1]);
+        let qs_minus = (s > 1).then(|| light[s - 2]);
+        camera[t - 1].delta = false;
+        camera[t - 1].pdf_rev = match &qs {
+            Some(qs) => self.pdf(world, qs, qs_minus.as_ref(), &pt),
+            None => self.pdf_light_origin(world, &pt),
+        };
+        if let Some(pt_minus) = &pt_minus {
+            camera[t - 2].pdf_rev = match &qs {
+                Some(qs) => self.pdf(world, &pt, Some(qs), pt_minus),
+                None => self.pdf_light(world, &pt, pt_minus),
+            };
+        }
+        if let Some(qs) = &qs {
+            light[s - 1].delta = false;
+            light[s - 1].pdf_rev = self.pdf(world, &pt, pt_minus.as_ref(), qs);
+        }
+        if let (Some(qs), Some(qs_minus)) = (&qs, &qs_minus) {
+            light[s - 2].pdf_rev = self.pdf(world, qs, Some(&pt), qs_minus);
+        }
+
+        let remap = |pdf: Float| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+            // connecting to the eye only works for pinholes
+            let available = i > 1 || self.camera.is_some();
+            if available && !camera[i].delta && !camera[i - 1].delta {
+                sum += ratio;
+            }
+        }
+        let mut ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+            let delta_before = i > 0 && light[i - 1].delta;
+            if !light[i].delta && !delta_before {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        let mut camera_path = Vec::with_capacity(self.max_bounces as usize + 2);
+        let mut light_path = Vec::with_capacity(self.max_bounces as usize + 1);
+        self.camera_subpath(ray, world, sampler, features, &mut camera_path);
+        self.light_subpath(ray.time, world, sampler, features, &mut light_path);
+        if self.camera.is_some() {
+            self.splats.add_path();
+        }
+
+        let mut radiance = Color::BLACK;
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                // s + t vertices make s + t - 1 segments
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 1 > self.max_bounces as usize {
+                    continue;
+                }
+                if t == 1 && self.camera.is_none() {
+                    continue;
+                }
+                let (contribution, raster) = self.connect(world, &light_path, &camera_path, s, t, ray.time, sampler);
+                match raster {
+                    Some((i, j)) => self.splats.add(i, j, contribution),
+                    None => radiance = radiance + contribution,
+                }
+            }
+        }
+        radiance
+    }
+
+    fn splats(&self) -> Option<&SplatFilm> {
+        self.camera.is_some().then_some(&self.splats)
+    }
+}
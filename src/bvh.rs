@@ -0,0 +1,122 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::packet::RayPacket;
+use crate::ray::Ray;
+use crate::stats;
+
+const MAX_LEAF_SIZE: usize = 2;
+
+// Flattened bounding volume hierarchy over a list of object bounds. Interior nodes
+// are followed directly by their first child, leaves point at a run of `order`.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>,
+}
+
+struct BvhNode {
+    bounds: Aabb,
+    // leaves: first index into `order` and how many objects follow, interior nodes
+    // have no objects and keep the node index of their second child in `start`
+    start: usize,
+    count: usize,
+    axis: usize,
+}
+
+impl Bvh {
+    // Splits at the median centroid along the longest axis of the centroids.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh { nodes: vec![], order: (0..bounds.len()).collect() };
+        if !bounds.is_empty() {
+            bvh.build_node(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], start: usize, end: usize) -> usize {
+        let node_bounds = self.order[start + 1..end].iter()
+            .fold(bounds[self.order[start]], |b, i| b.union(&bounds[*i]));
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: node_bounds, start, count: end - start, axis: 0 });
+        if end - start <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        let centroid = |i: &usize| bounds[*i].centroid();
+        let first = centroid(&self.order[start]);
+        let centroid_bounds = self.order[start..end].iter()
+            .fold(Aabb::new(first, first), |b, i| b.union(&Aabb::new(centroid(i), centroid(i))));
+        let axis = centroid_bounds.longest_axis();
+        let mid = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(mid - start, |a, b| centroid(a)[axis].total_cmp(&centroid(b)[axis]));
+
+        self.build_node(bounds, start, mid);
+        let second_child = self.build_node(bounds, mid, end);
+        self.nodes[index] = BvhNode { bounds: node_bounds, start: second_child, count: 0, axis };
+        index
+    }
+
+    // Calls `hit_object` with every object whose node the ray reaches before the
+    // closest hit so far, it returns the distance of a closer hit if it found one.
+    pub fn traverse(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, mut hit_object: impl FnMut(usize, Float) -> Option<Float>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut closest = ray_t_max;
+        let mut stack = vec![0];
+        let mut visited = 0;
+        while let Some(index) = stack.pop() {
+            visited += 1;
+            let node = &self.nodes[index];
+            if node.bounds.hit(ray, ray_t_min, closest).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for object in &self.order[node.start..node.start + node.count] {
+                    if let Some(t) = hit_object(*object, closest) {
+                        closest = t;
+                    }
+                }
+            } else if ray.direction[node.axis] < 0.0 {
+                // visit the near child first so the far one can often be skipped
+                stack.push(index + 1);
+                stack.push(node.start);
+            } else {
+                stack.push(node.start);
+                stack.push(index + 1);
+            }
+        }
+        stats::record(|s| s.bvh_nodes_visited += visited);
+    }
+
+    // `traverse` for a packet of rays. The lanes that reach a node go on to its
+    // children and every lane takes the way through the tree its ray would take on
+    // its own, so `hit_object` sees the same objects in the same order for each ray.
+    // It gets the lanes to test and updates their closest hits.
+    pub fn traverse_packet(&self, packet: &RayPacket, ray_t_min: Float, closest: &mut [Float], mut hit_object: impl FnMut(usize, u32, &mut [Float])) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![(0, packet.all_lanes())];
+        let mut visited = 0;
+        while let Some((index, lanes)) = stack.pop() {
+            visited += 1;
+            let node = &self.nodes[index];
+            let lanes = packet.hit_bounds(&node.bounds, lanes, ray_t_min, closest);
+            if lanes == 0 {
+                continue;
+            }
+            if node.count > 0 {
+                for object in &self.order[node.start..node.start + node.count] {
+                    hit_object(*object, lanes, closest);
+                }
+            } else if packet.negative(node.axis) {
+                stack.push((index + 1, lanes));
+                stack.push((node.start, lanes));
+            } else {
+                stack.push((node.start, lanes));
+                stack.push((index + 1, lanes));
+            }
+        }
+        stats::record(|s| s.bvh_nodes_visited += visited);
+    }
+}
@@ -1,62 +1,341 @@
 use std::fs::File;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 use std::io::Write;
-use rand::Rng;
-use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::animation::CameraAnimation;
+use crate::aov::Aov;
+use crate::bdpt::PinholeCamera;
+use crate::color::Color;
+use crate::checkpoint::Checkpoint;
+use crate::film::{Features, Film};
+use crate::filter::{Filter, FilterKind, FilterSampler};
+use crate::float::Float;
+use crate::heatmap::{Heatmap, HeatmapBuffer};
+use crate::hittable::{HitRecord, HittableList};
+use crate::integrator::IntegratorKind;
+use crate::metropolis;
+use crate::packet::RayPacket;
 use crate::{IMAGE_HEIGHT, IMAGE_WIDTH, libs};
-use crate::libs::{degrees_to_radians};
+use crate::libs::{degrees_to_radians, sample_unit_disk};
+use crate::progress::{CancelToken, Progress, ProgressTracker};
+use crate::projection::{Projection, Stereo};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::stats::{self, Stats};
 use crate::vec3::{Point3, Vec3};
 
+// Stratified sampling needs a sample count up front, time limited renders cap it here.
+const MAX_STRATA: u32 = 4096;
+
 #[derive(Debug, Default)]
 pub struct Camera {
-    pub position: Vec3,
+    pub position: Point3,
     pub forward: Vec3,
     pub world_up: Vec3,
     pub u: Vec3,
     pub v: Vec3,
 
-    pub focal_length: f64,
+    pub focal_length: Float,
     pub camera_center: Point3,
     pub pixel_delta_u: Vec3,
     pub pixel_delta_v: Vec3,
     //viewport_upper_left: Vec3,
-    pub pixel00_loc: Vec3,
-    pub max_bounces: u8,
-    pub samples_per_pixel: u8,
-    pub pixel_sample_scale: f64,
-    pub vfov: f64,
+    pub pixel00_loc: Point3,
+    pub integrator: IntegratorKind,
+    pub max_bounces: u32,
+    pub samples_per_pixel: u32,
+    pub time_limit: Option<Duration>,
+    pub russian_roulette_depth: u32,
+    // trace wavelengths instead of RGB, for path and mlt
+    pub spectral: bool,
+    // camera rays traced together through the BVH, 1 traces them one by one
+    pub packet_size: u32,
+    pub vfov: Float,
+    pub defocus_angle: Float,
+    pub defocus_disk_u: Vec3,
+    pub defocus_disk_v: Vec3,
+    // rays are spread over [shutter_open, shutter_close] seconds for motion blur
+    pub shutter_open: Float,
+    pub shutter_close: Float,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub filter: Filter,
+    pub min_samples: u32,
+    pub adaptive_threshold: Float,
+    pub aovs: Vec<Aov>,
+    pub cryptomatte: bool,
+    pub heatmaps: Vec<Heatmap>,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+    // the film is saved here every `checkpoint_interval` and when the render ends,
+    // stereo renders add .left and .right
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Duration,
+    // picked up by the next render, one per eye
+    pub resume_from: Vec<Checkpoint>,
 }
 
 impl Camera {
-    pub fn render(&mut self, world: &HittableList) -> Vec<Vec<Vec3>> {
+    // `progress` gets a report after every finished row, `cancel` stops the render
+    // after the current row and leaves a partial image.
+    pub fn render(&mut self, world: &HittableList, progress: Option<&Sender<Progress>>, cancel: Option<&CancelToken>) -> Film {
         self.initialize();
-        //let mut contents = format!("P3\n{} {}\n255\n", IMAGE_WIDTH, IMAGE_HEIGHT);
-        let mut pixel_matrix: Vec<Vec<Vec3>> = vec![vec![Vec3::new(0.0, 0.0, 0.0); IMAGE_WIDTH as usize]; IMAGE_HEIGHT as usize];
-
-        for j in 0..IMAGE_HEIGHT {
-            for i in 0..IMAGE_WIDTH {
-                let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
-                for sample in 0..self.samples_per_pixel {
-                    let r = self.construct_ray(i, j);
-                    pixel_color = pixel_color + Self::ray_color(&r, &world, self.max_bounces);
+        let mut resume = std::mem::take(&mut self.resume_from).into_iter();
+        let eyes = if self.stereo.is_some() { 2 } else { 1 };
+        let rows = eyes * self.samples_per_pixel as u64 * IMAGE_HEIGHT as u64;
+        let mut progress = ProgressTracker::new(progress, rows, self.time_limit);
+        let film = match self.stereo {
+            None => self.render_film(world, 0.0, self.time_limit, resume.next(), &mut progress, cancel),
+            Some(stereo) => {
+                // both eyes share the time budget
+                let time_limit = self.time_limit.map(|limit| limit / 2);
+                let left = self.render_film(world, -1.0, time_limit, resume.next(), &mut progress, cancel);
+                let right = self.render_film(world, 1.0, time_limit, resume.next(), &mut progress, cancel);
+                Film::join(left, right, stereo.layout)
+            }
+        };
+        progress.finish();
+        film
+    }
+
+    // Renders the view of one eye, -1 left, 1 right and 0 without stereo.
+    // Continuing from a checkpoint gives the same image as an uninterrupted render.
+    fn render_film(&self, world: &HittableList, eye: Float, time_limit: Option<Duration>, resume: Option<Checkpoint>, progress: &mut ProgressTracker, cancel: Option<&CancelToken>) -> Film {
+        if let IntegratorKind::Metropolis(settings) = self.integrator {
+            return metropolis::render(self, world, eye, settings, time_limit, progress, cancel);
+        }
+        let start = Instant::now();
+
+        let mut state = resume.unwrap_or_else(|| Checkpoint {
+            sampler: self.sampler,
+            seed: self.seed,
+            strata: self.samples_per_pixel.min(MAX_STRATA),
+            next_sample: 0,
+            film: Film::new(IMAGE_WIDTH, IMAGE_HEIGHT, &self.aovs, self.cryptomatte),
+        });
+        if !self.heatmaps.is_empty() {
+            // the heatmaps read the counters, and cover this run only
+            stats::enable();
+            let pixel_count = state.film.pixels.len();
+            state.film.heatmaps = self.heatmaps.iter().map(|heatmap| HeatmapBuffer::new(*heatmap, pixel_count)).collect();
+        }
+        let mut sampler = self.sampler.build(state.strata, self.seed);
+        let mut integrator = self.integrator.build(self.max_bounces, self.russian_roulette_depth, self.seed, self.pinhole(eye), self.spectral);
+        let filter_sampler = FilterSampler::new(self.filter);
+        let checkpoint_path = self.checkpoint.as_ref().map(|path| match eye {
+            e if e < 0.0 => format!("{}.left", path),
+            e if e > 0.0 => format!("{}.right", path),
+            _ => path.clone(),
+        });
+        let mut last_checkpoint = Instant::now();
+        let rows_per_eye = self.samples_per_pixel as u64 * IMAGE_HEIGHT as u64;
+        let first_row = if eye > 0.0 { rows_per_eye } else { 0 };
+        progress.skip_rows(state.next_sample as u64 * IMAGE_HEIGHT as u64);
+        let mut cancelled = false;
+        // heatmaps charge each pixel for its own work, which packets share out
+        let group_size = match self.packet_size > 1 && integrator.takes_first_hit() && self.heatmaps.is_empty() {
+            true => self.packet_size as usize,
+            false => 1,
+        };
+
+        // one pass takes one more sample of every pixel that has not converged yet,
+        // so a time limit still leaves an evenly sampled image behind
+        'passes: for sample in state.next_sample..self.samples_per_pixel {
+            integrator.start_pass(world, sample, self.shutter_open);
+            let mut active = false;
+            for j in 0..IMAGE_HEIGHT {
+                if cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+                    cancelled = true;
+                    break 'passes;
                 }
+                let mut rays = 0;
+                let pending: Vec<u32> = (0..IMAGE_WIDTH).filter(|&i| !state.film.pixel_mut(i, j).converged).collect();
+                for group in pending.chunks(group_size) {
+                    let first_hits = match group_size {
+                        1 => vec![None],
+                        _ => self.trace_packet(group, j, sample, eye, sampler.as_mut(), &filter_sampler, world),
+                    };
+                    for (&i, first_hit) in group.iter().zip(first_hits) {
+                        active = true;
+                        let heat_start = (!self.heatmaps.is_empty()).then(|| (stats::local(), Instant::now()));
+                        sampler.start_pixel_sample(i, j, sample);
+                        let (r, weight) = self.construct_ray(i, j, eye, sampler.as_mut(), &filter_sampler);
+                        let mut features = Features::default();
+                        let traced = r.is_some();
+                        let sample_color = match r {
+                            Some(r) => match first_hit {
+                                Some(hit) => integrator.li_from_hit(&r, hit, world, sampler.as_mut(), &mut features),
+                                None => integrator.li(&r, world, sampler.as_mut(), &mut features),
+                            },
+                            None => Color::BLACK,
+                        };
 
-                let color = self.pixel_sample_scale * pixel_color;
-                //contents.push_str(libs::write_color(&color).as_str());
-                pixel_matrix[j as usize][i as usize] = color;
+                        let pixel = state.film.pixel_mut(i, j);
+                        pixel.add_sample(sample_color, weight, &features);
+                        pixel.converged = pixel.has_converged(self.adaptive_threshold, self.min_samples);
+                        state.film.add_passes(i, j, &features);
+                        if let Some((counts, time)) = heat_start {
+                            Self::add_heat(&mut state.film, i, j, &counts, time);
+                        }
+                        rays += features.rays as u64;
+                        if traced {
+                            stats::record(|s| {
+                                s.primary_rays += 1;
+                                s.secondary_rays += features.rays.saturating_sub(1) as u64;
+                                s.paths += 1;
+                                s.path_rays += features.rays as u64;
+                            });
+                        }
+                    }
+                }
+                progress.row_finished(rays);
+            }
+            if let Some(splats) = integrator.splats() {
+                splats.drain_into(&mut state.film);
+            }
+
+            state.next_sample = sample + 1;
+            let out_of_time = time_limit.is_some_and(|limit| start.elapsed() >= limit);
+            if !active {
+                // every pixel converged, the rest of the passes are done too
+                progress.skip_to(first_row + rows_per_eye);
+                break;
+            }
+            if out_of_time {
+                break;
+            }
+            if let Some(path) = &checkpoint_path {
+                if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                    Self::save_checkpoint(&state, path);
+                    last_checkpoint = Instant::now();
+                }
             }
         }
 
-        pixel_matrix
+        if cancelled {
+            if let Some(splats) = integrator.splats() {
+                splats.drain_into(&mut state.film);
+            }
+            // the film holds part of a pass now, the last periodic checkpoint is the
+            // one to resume from
+            return state.film;
+        }
+        if let Some(path) = &checkpoint_path {
+            Self::save_checkpoint(&state, path);
+        }
+        state.film
+    }
+
+    // First hits of the camera rays of a group of pixels in row `j`, traced as one
+    // packet. The samplers restart at every pixel, so the rays are the same ones the
+    // pixels construct again afterwards. None for every pixel when the rays do not
+    // point the same way and each traces its own.
+    #[allow(clippy::too_many_arguments)]
+    fn trace_packet(&self, group: &[u32], j: u32, sample: u32, eye: Float, sampler: &mut dyn Sampler, filter_sampler: &FilterSampler, world: &HittableList) -> Vec<Option<Option<HitRecord>>> {
+        let mut rays = Vec::with_capacity(group.len());
+        let lanes: Vec<Option<usize>> = group.iter().map(|&i| {
+            sampler.start_pixel_sample(i, j, sample);
+            let (r, _) = self.construct_ray(i, j, eye, sampler, filter_sampler);
+            r.map(|r| {
+                rays.push(r);
+                rays.len() - 1
+            })
+        }).collect();
+        match RayPacket::new(&rays).and_then(|packet| world.hit_packet(&packet, 0.001, Float::INFINITY)) {
+            Some(hits) => lanes.into_iter().map(|lane| lane.map(|lane| hits[lane])).collect(),
+            None => vec![None; group.len()],
+        }
+    }
+
+    fn add_heat(film: &mut Film, i: u32, j: u32, before: &Stats, start: Instant) {
+        let after = stats::local();
+        let index = (j * film.width + i) as usize;
+        for buffer in &mut film.heatmaps {
+            let value = match buffer.heatmap {
+                Heatmap::BvhNodes => (after.bvh_nodes_visited - before.bvh_nodes_visited) as Float,
+                Heatmap::Tests => (after.sphere_tests + after.volume_tests - before.sphere_tests - before.volume_tests) as Float,
+                Heatmap::Time => start.elapsed().as_secs_f64() as Float * 1e6,
+            };
+            buffer.add(index, value);
+        }
+    }
+
+    // A failed checkpoint is not worth losing the render over.
+    fn save_checkpoint(state: &Checkpoint, path: &str) {
+        if let Err(e) = state.save(path) {
+            eprintln!("could not write checkpoint {}: {}", path, e);
+        }
     }
 
-    fn construct_ray(&self, i: u32, j: u32) -> Ray {
-        let offset = Self::sample_square();
-        let pixel_center = self.pixel00_loc
-            + ((i as f64 + offset.x) * self.pixel_delta_u)
-            + ((j as f64 + offset.y) * self.pixel_delta_v);
-        let ray_direction = pixel_center - self.camera_center;
-        Ray::new(self.camera_center, ray_direction)
+    pub fn animate(&mut self, animation: &CameraAnimation, time: Float) {
+        if let Some(track) = &animation.position {
+            self.position = Point3::ORIGIN + track.sample(time);
+        }
+        if let Some(track) = &animation.forward {
+            self.forward = track.sample(time).normalize();
+        }
+    }
+
+    // Returns the camera ray for a sample of pixel (i, j) together with its filter
+    // weight, the ray is None where the projection does not cover the image.
+    pub fn construct_ray(&self, i: u32, j: u32, eye: Float, sampler: &mut dyn Sampler, filter_sampler: &FilterSampler) -> (Option<Ray>, Float) {
+        let (offset_x, offset_y, weight) = filter_sampler.sample(sampler.get_2d());
+        let lens = sample_unit_disk(sampler.get_2d());
+        let time = self.shutter_open + sampler.get_1d() * (self.shutter_close - self.shutter_open);
+
+        if self.projection != Projection::Perspective {
+            let x = 2.0 * (i as Float + 0.5 + offset_x) / IMAGE_WIDTH as Float - 1.0;
+            let y = 2.0 * (j as Float + 0.5 + offset_y) / IMAGE_HEIGHT as Float - 1.0;
+            let aspect = IMAGE_WIDTH as Float / IMAGE_HEIGHT as Float;
+            let ray = self.projection.camera_ray(x, y, aspect, self.vfov).map(|(offset, direction)| {
+                let (offset, direction) = match self.stereo {
+                    Some(stereo) if eye != 0.0 => stereo.eye_ray(self.projection, offset, direction, eye),
+                    _ => (offset, direction),
+                };
+                Ray::new(self.camera_center + self.to_world(offset), self.to_world(direction), time)
+            });
+            return (ray, weight);
+        }
+
+        let (eye_center, pixel00) = self.eye_position(eye);
+        let pixel_center = pixel00
+            + ((i as Float + offset_x) * self.pixel_delta_u)
+            + ((j as Float + offset_y) * self.pixel_delta_v);
+        let ray_origin = if self.defocus_angle <= 0.0 {
+            eye_center
+        } else {
+            eye_center + lens.0 * self.defocus_disk_u + lens.1 * self.defocus_disk_v
+        };
+        let ray_direction = pixel_center - ray_origin;
+        (Some(Ray::new(ray_origin, ray_direction, time)), weight)
+    }
+
+    // Centre of the eye and of pixel (0, 0) for a perspective camera.
+    fn eye_position(&self, eye: Float) -> (Point3, Point3) {
+        match self.stereo.filter(|_| eye != 0.0) {
+            Some(stereo) => {
+                // shift the eye sideways and the focus plane point with it, so that the
+                // eye rays still cross the centre ray on the convergence plane
+                let shift = eye * stereo.ipd / 2.0 * self.u;
+                (self.camera_center + shift, self.pixel00_loc + shift * (1.0 - self.focal_length / stereo.convergence))
+            }
+            None => (self.camera_center, self.pixel00_loc),
+        }
+    }
+
+    // The camera as a pinhole light paths can connect to, None with a lens or a
+    // projection other than perspective.
+    fn pinhole(&self, eye: Float) -> Option<PinholeCamera> {
+        if self.projection != Projection::Perspective || self.defocus_angle > 0.0 {
+            return None;
+        }
+        let (eye_center, pixel00) = self.eye_position(eye);
+        Some(PinholeCamera::new(eye_center, pixel00, self.pixel_delta_u, self.pixel_delta_v, IMAGE_WIDTH, IMAGE_HEIGHT))
+    }
+
+    // Camera space, x right, y up and looking down -z, to world space.
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.u + v.y * self.v + v.z * self.forward.normalize()
     }
 
     fn recalculate_camera_vectors(&mut self) {
@@ -66,48 +345,32 @@ impl Camera {
     fn initialize(&mut self) {
         self.recalculate_camera_vectors();
         let theta = degrees_to_radians(self.vfov);
-        let h = f64::tan(theta / 2.0);
+        let h = Float::tan(theta / 2.0);
         let viewport_height = 2.0 * h * self.focal_length;
-        let viewport_width = viewport_height * ((IMAGE_WIDTH as f64) / (IMAGE_HEIGHT as f64));
+        let viewport_width = viewport_height * ((IMAGE_WIDTH as Float) / (IMAGE_HEIGHT as Float));
         self.camera_center = self.position;
-        self.v = self.world_up.normalize();
-        self.u = self.forward.cross(&self.v).normalize();
+        // the camera looks down -forward, pixel columns run along u and rows along
+        // v, with the handedness the viewer has always had
+        self.u = self.forward.cross(&self.world_up).normalize();
+        self.v = self.u.cross(&self.forward).normalize();
 
-        let viewport_u = Vec3::new(viewport_width, 0.0, 0.0) * self.u;
-        let viewport_v = Vec3::new(0.0, viewport_height, 0.0) * self.v;
+        let viewport_u = viewport_width * self.u;
+        let viewport_v = viewport_height * self.v;
 
-        self.pixel_delta_u = viewport_u / (IMAGE_WIDTH as f64);
-        self.pixel_delta_v = viewport_v / (IMAGE_HEIGHT as f64);
+        self.pixel_delta_u = viewport_u / (IMAGE_WIDTH as Float);
+        self.pixel_delta_v = viewport_v / (IMAGE_HEIGHT as Float);
 
         let viewport_upper_left = self.camera_center
             - (self.forward * self.focal_length) - viewport_u / 2.0 - viewport_v / 2.0;
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
-    }
-
-    fn ray_color(ray: &Ray, world: &HittableList, bounces_left: u8) -> Vec3 {
-        let mut hit_record = HitRecord::empty();
-        if bounces_left > 0 && world.hit(ray, 0.001, f64::INFINITY, &mut hit_record) {
-            let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
-            let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
-            if let Some(material) = hit_record.material.as_ref() {
-                if material.scatter(ray, &hit_record, &mut attenuation, &mut scattered) {
-                    return Self::ray_color(&scattered, world, bounces_left - 1) * attenuation;
-                }
-            }
-            //return Vec3::new(0.0, 0.0, 0.0);
-        }
-
-        let unit = ray.direction.normalize();
-        let a = 0.5 * (unit.y + 1.0);
-        (1.0 - a) * Vec3::new(1.0, 1.0, 1.0) + a * Vec3::new(0.5, 0.7, 1.0)
-    }
 
-    fn sample_square() -> Vec3 {
-        let mut rand = rand::thread_rng();
-        Vec3::new(rand.gen_range(-0.5..0.5), rand.gen_range(-0.5..0.5), 0.0)
+        // the viewport sits at focal_length, so that is also the plane in focus
+        let defocus_radius = self.focal_length * degrees_to_radians(self.defocus_angle / 2.0).tan();
+        self.defocus_disk_u = self.u * defocus_radius;
+        self.defocus_disk_v = self.v * defocus_radius;
     }
 
-    pub fn new(position: Vec3, focal_length: f64, max_bounces: u8, samples_per_pixel: u8, vfov: f64) -> Camera {
+    pub fn new(position: Point3, focal_length: Float, max_bounces: u32, samples_per_pixel: u32, vfov: Float) -> Camera {
         Camera {
             position,
             forward: Vec3::new(0.0, 0.0, 1.0),
@@ -116,15 +379,103 @@ impl Camera {
             v: Vec3::new(0.0, 0.0, 0.0),
 
             focal_length,
-            pixel00_loc: Vec3::new(0.0, 0.0, 0.0),
-            camera_center: Point3::new(0.0, 0.0, 0.0),
+            pixel00_loc: Point3::ORIGIN,
+            camera_center: Point3::ORIGIN,
             pixel_delta_u: Vec3::new(0.0, 0.0, 0.0),
             pixel_delta_v: Vec3::new(0.0, 0.0, 0.0),
-            pixel_sample_scale: 1.0 / (samples_per_pixel as f64),
+            integrator: IntegratorKind::Path,
             max_bounces,
             samples_per_pixel,
+            time_limit: None,
+            russian_roulette_depth: 3,
+            spectral: false,
+            packet_size: 1,
             vfov,
+            defocus_angle: 0.0,
+            defocus_disk_u: Vec3::new(0.0, 0.0, 0.0),
+            defocus_disk_v: Vec3::new(0.0, 0.0, 0.0),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            filter: Filter::new(FilterKind::Box, 0.5),
+            min_samples: 16,
+            adaptive_threshold: 0.0,
+            aovs: vec![],
+            cryptomatte: false,
+            heatmaps: vec![],
+            projection: Projection::Perspective,
+            stereo: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume_from: vec![],
         }
         //camera.initialize()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::Aabb;
+    use crate::hittable::Sphere;
+    use crate::material::Material;
+    use crate::test_file::TestFile;
+    use crate::volume::Volume;
+
+    // Spheres for the packet kernel, one of them moving, and smoke for the ray by
+    // ray fallback.
+    fn scene() -> HittableList {
+        let mut world = HittableList::new();
+        let ground = Material::Lambertian { albedo: Color::new(0.8, 0.2, 0.2) };
+        world.push_named("ground", Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, Some(ground))));
+        let ball = Material::Metal { albedo: Color::new(0.9, 0.9, 0.9), fuzziness: 0.3 };
+        world.push_named("ball", Box::new(Sphere::moving(Point3::new(0.0, 0.0, -1.5), Point3::new(0.0, 0.3, -1.5), 0.5, Some(ball))));
+        let bounds = Aabb::new(Point3::new(-1.2, -0.5, -2.5), Point3::new(-0.2, 0.5, -1.5));
+        world.push_named("smoke", Box::new(Volume::procedural_smoke(bounds, 16, 3)));
+        world.build_bvh();
+        world
+    }
+
+    fn render(world: &HittableList, sampler: SamplerKind, packet_size: u32) -> Vec<Vec<Color>> {
+        let mut camera = Camera::new(Point3::ORIGIN, 1.0, 3, 1, 90.0);
+        camera.sampler = sampler;
+        camera.shutter_close = 1.0;
+        camera.packet_size = packet_size;
+        camera.render(world, None, None).image()
+    }
+
+    #[test]
+    fn resumed_render_matches_uninterrupted_render() {
+        let world = scene();
+        let camera = || {
+            let mut camera = Camera::new(Point3::ORIGIN, 1.0, 3, 3, 90.0);
+            camera.sampler = SamplerKind::Stratified;
+            camera
+        };
+        let file = TestFile::new("resume");
+
+        // a time limit that has run out stops after the first pass and saves it
+        let mut interrupted = camera();
+        interrupted.time_limit = Some(Duration::ZERO);
+        interrupted.checkpoint = Some(file.path().to_string());
+        interrupted.render(&world, None, None);
+        let checkpoint = Checkpoint::load(file.path()).unwrap();
+        assert_eq!(checkpoint.next_sample, 1);
+
+        let mut resumed = camera();
+        resumed.resume_from = vec![checkpoint];
+        assert!(resumed.render(&world, None, None).image() == camera().render(&world, None, None).image());
+    }
+
+    #[test]
+    fn packets_render_the_same_image_as_single_rays() {
+        let world = scene();
+        for sampler in [SamplerKind::Independent, SamplerKind::Sobol] {
+            let single = render(&world, sampler, 1);
+            for packet_size in crate::packet::PACKET_SIZES {
+                assert!(render(&world, sampler, packet_size) == single, "packets of {} with {:?}", packet_size, sampler);
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs;
+use std::io;
+use crate::aov::{Aov, AovBuffer};
+use crate::cryptomatte::CoverageLayer;
+use crate::film::{Film, FilmPixel};
+use crate::float::{to_f64, Float};
+use crate::sampler::SamplerKind;
+use crate::color::Color;
+use crate::vec3::Vec3;
+
+const MAGIC: &[u8; 8] = b"RTCHECK2";
+
+// Bytes `save` writes per film pixel: four colors and vectors, four floats and two u32.
+const PIXEL_BYTES: usize = 4 * 24 + 4 * 8 + 2 * 4;
+
+// Everything a render needs to carry on where it stopped. Samplers are pure
+// functions of pixel, sample index, dimension and seed, so the seed and the next
+// pass are all the random number state there is.
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    // sample count the stratified sampler divides its strata for
+    pub strata: u32,
+    pub next_sample: u32,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    // Checks the checkpoint belongs to a render with these settings.
+    pub fn check(&self, sampler: SamplerKind, seed: u64, width: u32, height: u32, aovs: &[Aov], cryptomatte: bool) -> Result<(), String> {
+        if self.film.width != width || self.film.height != height {
+            return Err(format!("checkpoint is {}x{}, the render is {}x{}", self.film.width, self.film.height, width, height));
+        }
+        if self.sampler != sampler || self.seed != seed {
+            return Err(format!("checkpoint used the {} sampler with seed {}", self.sampler.name(), self.seed));
+        }
+        let saved_aovs: Vec<Aov> = self.film.aovs.iter().map(|buffer| buffer.aov).collect();
+        if saved_aovs != aovs || self.film.cryptomatte.is_empty() == cryptomatte {
+            return Err("checkpoint was rendered with different AOVs".to_string());
+        }
+        Ok(())
+    }
+
+    // Writes to a temporary file first, so a crash while saving keeps the old checkpoint.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut out = Writer(MAGIC.to_vec());
+        out.string(self.sampler.name());
+        out.u64(self.seed);
+        out.u32(self.strata);
+        out.u32(self.next_sample);
+
+        let film = &self.film;
+        out.u32(film.width);
+        out.u32(film.height);
+        for pixel in &film.pixels {
+            out.color(pixel.weighted_sum);
+            out.color(pixel.unweighted_sum);
+            out.f64(pixel.weight_sum);
+            out.color(pixel.albedo_sum);
+            out.vec3(pixel.normal_sum);
+            out.f64(pixel.depth_sum);
+            out.u32(pixel.sample_count);
+            out.f64(pixel.mean);
+            out.f64(pixel.m2);
+            out.u32(pixel.converged as u32);
+        }
+        out.u32(film.aovs.len() as u32);
+        for buffer in &film.aovs {
+            out.string(buffer.aov.name());
+            buffer.data.iter().for_each(|v| out.f32(*v));
+        }
+        out.u32(film.cryptomatte.len() as u32);
+        for layer in &film.cryptomatte {
+            out.string(layer.name);
+            for ids in &layer.pixels {
+                out.u32(ids.len() as u32);
+                for (id, coverage) in ids {
+                    out.u32(*id);
+                    out.f32(*coverage);
+                }
+            }
+        }
+        out.u64(film.light_paths);
+        out.u32(film.splats.len() as u32);
+        film.splats.iter().for_each(|v| out.color(*v));
+
+        let temporary = format!("{}.tmp", path);
+        fs::write(&temporary, out.0)?;
+        fs::rename(temporary, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<Checkpoint> {
+        let bytes = fs::read(path)?;
+        let mut input = Reader { bytes: &bytes, position: 0, path };
+        if input.take(MAGIC.len())? != MAGIC {
+            return Err(input.invalid("not a checkpoint file"));
+        }
+        let sampler = SamplerKind::parse(&input.string()?).ok_or_else(|| input.invalid("unknown sampler"))?;
+        let seed = input.u64()?;
+        let strata = input.u32()?;
+        let next_sample = input.u32()?;
+
+        let width = input.u32()?;
+        let height = input.u32()?;
+        // a corrupt header must not get to allocate the film
+        let pixel_count = width.checked_mul(height).ok_or_else(|| input.invalid("bad resolution"))? as usize;
+        if (pixel_count as u64) * (PIXEL_BYTES as u64) > input.remaining() as u64 {
+            return Err(input.invalid("file is truncated"));
+        }
+        let mut film = Film::new(width, height, &[], false);
+        for pixel in film.pixels.iter_mut() {
+            *pixel = FilmPixel {
+                weighted_sum: input.color()?,
+                unweighted_sum: input.color()?,
+                weight_sum: input.f64()?,
+                albedo_sum: input.color()?,
+                normal_sum: input.vec3()?,
+                depth_sum: input.f64()?,
+                sample_count: input.u32()?,
+                mean: input.f64()?,
+                m2: input.f64()?,
+                converged: input.u32()? != 0,
+            };
+        }
+        for _ in 0..input.u32()? {
+            let aov = Aov::parse(&input.string()?).ok_or_else(|| input.invalid("unknown AOV"))?;
+            let mut buffer = AovBuffer::new(aov, pixel_count);
+            for v in buffer.data.iter_mut() {
+                *v = input.f32()?;
+            }
+            film.aovs.push(buffer);
+        }
+        for _ in 0..input.u32()? {
+            let name = match input.string()?.as_str() {
+                "object" => "object",
+                "material" => "material",
+                _ => return Err(input.invalid("unknown cryptomatte layer")),
+            };
+            let mut layer = CoverageLayer::new(name, pixel_count);
+            for ids in layer.pixels.iter_mut() {
+                for _ in 0..input.u32()? {
+                    ids.push((input.u32()?, input.f32()?));
+                }
+            }
+            film.cryptomatte.push(layer);
+        }
+        film.light_paths = input.u64()?;
+        for _ in 0..input.u32()? {
+            film.splats.push(input.color()?);
+        }
+
+        Ok(Checkpoint { sampler, seed, strata, next_sample, film })
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    // Floats are stored as f64 whatever the precision, so checkpoints carry over.
+    fn f64(&mut self, v: Float) {
+        self.0.extend_from_slice(&to_f64(v).to_le_bytes());
+    }
+
+    fn vec3(&mut self, v: Vec3) {
+        self.f64(v.x);
+        self.f64(v.y);
+        self.f64(v.z);
+    }
+
+    fn color(&mut self, c: Color) {
+        self.f64(c.r);
+        self.f64(c.g);
+        self.f64(c.b);
+    }
+
+    fn string(&mut self, v: &str) {
+        self.u32(v.len() as u32);
+        self.0.extend_from_slice(v.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    path: &'a str,
+}
+
+impl Reader<'_> {
+    fn invalid(&self, msg: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", self.path, msg))
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.remaining() < n {
+            return Err(self.invalid("file is truncated"));
+        }
+        self.position += n;
+        Ok(&self.bytes[self.position - n..self.position])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> io::Result<Float> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()) as Float)
+    }
+
+    fn vec3(&mut self) -> io::Result<Vec3> {
+        Ok(Vec3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    fn color(&mut self) -> io::Result<Color> {
+        Ok(Color::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| self.invalid("bad string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::Features;
+    use crate::test_file::TestFile;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut film = Film::new(3, 2, &[Aov::Depth, Aov::Albedo], true);
+        for (n, (i, j)) in [(0, 0), (2, 1), (2, 1), (1, 0)].into_iter().enumerate() {
+            let n = n as Float;
+            let features = Features { albedo: Color::new(0.5, n, 0.25), normal: Vec3::new(0.0, 1.0, 0.0), depth: 1.5 + n, object_id: 7, ..Features::default() };
+            film.pixel_mut(i, j).add_sample(Color::new(0.1 * n, 0.2, 1.0 / 3.0), 0.75, &features);
+            film.add_passes(i, j, &features);
+        }
+        film.pixel_mut(1, 0).converged = true;
+        film.splats = vec![Color::new(0.0, 1e-3, 2.0); 6];
+        film.light_paths = 12345;
+        let checkpoint = Checkpoint { sampler: SamplerKind::Sobol, seed: 42, strata: 16, next_sample: 3, film };
+
+        let file = TestFile::new("checkpoint");
+        checkpoint.save(file.path()).unwrap();
+        let loaded = Checkpoint::load(file.path());
+        // Debug prints floats exactly, so equal text is an equal checkpoint
+        assert_eq!(format!("{:?}", loaded.unwrap()), format!("{:?}", checkpoint));
+    }
+
+    #[test]
+    fn load_rejects_a_header_the_file_cannot_hold() {
+        let file = TestFile::new("corrupt");
+        let mut header = Writer(MAGIC.to_vec());
+        header.string("independent");
+        header.u64(0);
+        header.u32(1);
+        header.u32(0);
+        for (width, height) in [(u32::MAX, 2), (65536, 65535), (2, 1)] {
+            let mut out = Writer(header.0.clone());
+            out.u32(width);
+            out.u32(height);
+            fs::write(file.path(), &out.0).unwrap();
+            let error = Checkpoint::load(file.path()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}x{}", width, height);
+        }
+    }
+}
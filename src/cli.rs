@@ -0,0 +1,411 @@
+use std::time::Duration;
+use crate::aov::{Aov, ALL_AOVS};
+use crate::filter::{Filter, FilterKind};
+use crate::float::{to_f64, Float};
+use crate::heatmap::Heatmap;
+use crate::integrator::IntegratorKind;
+use crate::material::Dispersion;
+use crate::packet::PACKET_SIZES;
+use crate::projection::{Projection, Stereo, StereoLayout};
+use crate::sampler::SamplerKind;
+use crate::color::Color;
+
+pub const USAGE: &str = "\
+usage: RustTracer [options]
+
+  --output <file.ppm>            render once and write the image instead of opening a window
+  --spp <n>                      samples per pixel (default 1, unlimited with --time-limit)
+  --time-limit <seconds>         keep adding samples until this much time has passed
+  --integrator <name>            path, bdpt for bidirectional path tracing, ppm for progressive
+                                 photon mapping, or a debug view: normals, depth[:far], uv,
+                                 albedo, uv-grid or ao[:radius]; M cycles through path and
+                                 the debug views in the viewer
+                                 mlt and mlt-bdpt are Metropolis light transport over paths
+                                 of path and bdpt, --spp counts mutations per pixel
+  --chains <n>                   Markov chains mlt runs in parallel, the seed and this count
+                                 decide the image (default 8)
+  --large-step <probability>     share of mlt mutations that start over with a new path (default 0.3)
+  --photons <n>                  photons ppm traces from the lights every pass (default 100000)
+  --gather-radius <distance>     radius ppm gathers photons in on the first pass, it shrinks
+                                 with every pass after (default 0.05)
+  --spectral                     trace wavelengths instead of RGB, with path or mlt
+  --dispersion <name>            how the glass ball's refraction index changes with wavelength
+                                 under --spectral: none, water, bk7 or sf11 (default water)
+  --packets <n>                  trace camera rays in packets of 4, 8 or 16 through the BVH,
+                                 with path; the image is the same as with single rays
+  --max-bounces <n>              longest path, Russian roulette ends most paths earlier (default 8)
+  --min-samples <n>              samples taken before adaptive sampling may stop (default 16)
+  --adaptive-threshold <error>   stop sampling a pixel once its relative error drops below this
+  --stats                        with --output, print ray and intersection counts at the end
+  --no-progress                  with --output, hide the progress bar shown on terminals; pressing
+                                 Enter there stops the render and keeps the image so far
+  --sample-count-output <file>   with --output, also write an image of samples taken per pixel
+  --aovs <list|all>              with --output, also write these comma separated passes as
+                                 <output>.<pass>.pfm: depth, normal, albedo, object_id,
+                                 material_id, diffuse_direct, diffuse_indirect, specular, emission
+  --cryptomatte <ranks>          with --output, also write object and material id coverage,
+                                 the <ranks> ids covering most of each pixel, as
+                                 <output>.crypto_<layer>.rank<n>.pfm plus a .json manifest
+  --heatmap <list>               with --output, also write false color debug views with a
+                                 legend as <output>.heatmap_<name>.ppm: bvh for BVH nodes
+                                 visited, tests for intersection tests, time for render time
+  --denoise                      denoise the image, N toggles this in the viewer
+  --sampler <name>               independent, stratified, halton or sobol
+  --seed <n>                     seed for the sample patterns (default 0)
+  --defocus-angle <degrees>      lens aperture cone angle, 0 for a pinhole
+  --projection <name>            perspective, orthographic[:view width], fisheye-equidistant[:fov],
+                                 fisheye-equisolid[:fov], cylindrical or equirectangular
+  --checkpoint <file>            with --output, save the render state here periodically
+                                 and when it ends
+  --checkpoint-interval <secs>   time between checkpoints (default 60)
+  --resume                       continue from --checkpoint, the image matches an uninterrupted
+                                 render; stratified renders keep the sample count they started with
+  --stereo <layout>              with --output, render both eyes as top-bottom or side-by-side,
+                                 panoramic projections use omni-directional stereo
+  --ipd <distance>               interpupillary distance for --stereo (default 0.064)
+  --convergence <distance>       distance at which the eye rays meet (default 2)
+  --shutter <open>,<close>       shutter interval in seconds, moving objects blur across it
+                                 (default 0,0)
+  --frames <first>-<last>        with --output, render this frame range of the animation as a
+                                 numbered sequence, frames whose file exists are skipped; a
+                                 run of # in the output name is replaced by the frame number
+  --fps <n>                      frames per second of the animation (default 24)
+  --filter <name>                box, tent, gaussian, mitchell or lanczos
+  --filter-radius <pixels>       reconstruction filter radius
+  --smoke                        add a procedural smoke plume to the scene
+  --lamp                         add a small bright lamp above the glass ball
+  --volume <file>                add a density grid loaded from a .grid file
+  --volume-temperature <file>    temperature grid for --volume, in kelvin
+  --volume-density <scale>       density multiplier for --volume (default 20)
+  --volume-emission <r,g,b>      constant emitted radiance for --volume
+";
+
+pub struct Options {
+    pub output: Option<String>,
+    pub samples_per_pixel: u32,
+    pub time_limit: Option<Duration>,
+    pub max_bounces: u32,
+    pub min_samples: u32,
+    pub adaptive_threshold: Float,
+    pub sample_count_output: Option<String>,
+    pub aovs: Vec<Aov>,
+    pub integrator: IntegratorKind,
+    pub spectral: bool,
+    pub dispersion: Dispersion,
+    pub packet_size: u32,
+    pub heatmaps: Vec<Heatmap>,
+    pub cryptomatte_ranks: usize,
+    pub denoise: bool,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub defocus_angle: Float,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Duration,
+    pub resume: bool,
+    pub shutter: (Float, Float),
+    pub frames: Option<(i64, i64)>,
+    pub fps: Float,
+    pub filter: Filter,
+    pub smoke: bool,
+    pub lamp: bool,
+    pub progress: bool,
+    pub stats: bool,
+    pub volume: Option<String>,
+    pub volume_temperature: Option<String>,
+    pub volume_density: Float,
+    pub volume_emission: Color,
+}
+
+impl Options {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            output: None,
+            samples_per_pixel: 1,
+            time_limit: None,
+            max_bounces: 8,
+            min_samples: 16,
+            adaptive_threshold: 0.0,
+            sample_count_output: None,
+            aovs: vec![],
+            integrator: IntegratorKind::Path,
+            spectral: false,
+            dispersion: Dispersion::parse("water").unwrap(),
+            packet_size: 1,
+            heatmaps: vec![],
+            cryptomatte_ranks: 0,
+            denoise: false,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            defocus_angle: 0.0,
+            projection: Projection::Perspective,
+            stereo: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: false,
+            shutter: (0.0, 0.0),
+            frames: None,
+            fps: 24.0,
+            filter: Filter::new(FilterKind::Box, FilterKind::Box.default_radius()),
+            smoke: false,
+            lamp: false,
+            progress: true,
+            stats: false,
+            volume: None,
+            volume_temperature: None,
+            volume_density: 20.0,
+            volume_emission: Color::BLACK,
+        };
+
+        let mut stereo_layout = None;
+        let mut ipd = 0.064;
+        let mut convergence = 2.0;
+        let mut filter_kind = FilterKind::Box;
+        let mut filter_radius = None;
+        let mut samples_per_pixel = None;
+        let mut photons = None;
+        let mut chains = None;
+        let mut large_step = None;
+        let mut gather_radius = None;
+        let mut dispersion = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--output" => options.output = Some(value()?),
+                "--spp" => {
+                    let count = parse_integer(&value()?)?;
+                    if count == 0 {
+                        return Err("samples per pixel must be positive, got 0".to_string());
+                    }
+                    samples_per_pixel = Some(count);
+                }
+                "--time-limit" => {
+                    let seconds = parse_number(&value()?)?;
+                    options.time_limit = Some(Duration::try_from_secs_f64(to_f64(seconds)).map_err(|e| e.to_string())?);
+                }
+                "--integrator" => {
+                    let name = value()?;
+                    options.integrator = IntegratorKind::parse(&name).ok_or_else(|| format!("unknown integrator {}", name))?;
+                }
+                "--chains" => {
+                    let count = parse_integer(&value()?)?;
+                    if count == 0 {
+                        return Err("chain count must be positive, got 0".to_string());
+                    }
+                    chains = Some(count);
+                }
+                "--large-step" => {
+                    let probability = parse_number(&value()?)?;
+                    if !(0.0..=1.0).contains(&probability) {
+                        return Err(format!("large step probability must be between 0 and 1, got {}", probability));
+                    }
+                    large_step = Some(probability);
+                }
+                "--photons" => {
+                    let count = parse_integer(&value()?)?;
+                    if count == 0 {
+                        return Err("photon count must be positive, got 0".to_string());
+                    }
+                    photons = Some(count);
+                }
+                "--gather-radius" => {
+                    let radius = parse_number(&value()?)?;
+                    if !(radius > 0.0 && radius.is_finite()) {
+                        return Err(format!("gather radius must be positive, got {}", radius));
+                    }
+                    gather_radius = Some(radius);
+                }
+                "--spectral" => options.spectral = true,
+                "--dispersion" => {
+                    let name = value()?;
+                    dispersion = Some(Dispersion::parse(&name).ok_or_else(|| format!("unknown dispersion {}", name))?);
+                }
+                "--packets" => {
+                    let size = parse_integer(&value()?)?;
+                    if !PACKET_SIZES.contains(&size) {
+                        return Err(format!("packet size must be 4, 8 or 16, got {}", size));
+                    }
+                    options.packet_size = size;
+                }
+                "--max-bounces" => options.max_bounces = parse_integer(&value()?)?,
+                "--min-samples" => options.min_samples = parse_integer(&value()?)?,
+                "--adaptive-threshold" => options.adaptive_threshold = parse_number(&value()?)?,
+                "--sample-count-output" => options.sample_count_output = Some(value()?),
+                "--aovs" => options.aovs = parse_aovs(&value()?)?,
+                "--heatmap" => {
+                    options.heatmaps = value()?
+                        .split(',')
+                        .map(|name| Heatmap::parse(name).ok_or_else(|| format!("unknown heatmap {}", name)))
+                        .collect::<Result<_, _>>()?;
+                }
+                "--cryptomatte" => options.cryptomatte_ranks = parse_integer(&value()?)?,
+                "--denoise" => options.denoise = true,
+                "--sampler" => {
+                    let name = value()?;
+                    options.sampler = SamplerKind::parse(&name).ok_or_else(|| format!("unknown sampler {}", name))?;
+                }
+                "--seed" => options.seed = parse_integer(&value()?)?,
+                "--defocus-angle" => options.defocus_angle = parse_number(&value()?)?,
+                "--projection" => {
+                    let name = value()?;
+                    options.projection = Projection::parse(&name).ok_or_else(|| format!("unknown projection {}", name))?;
+                }
+                "--checkpoint" => options.checkpoint = Some(value()?),
+                "--checkpoint-interval" => {
+                    let seconds = parse_number(&value()?)?;
+                    options.checkpoint_interval = Duration::try_from_secs_f64(to_f64(seconds)).map_err(|e| e.to_string())?;
+                }
+                "--resume" => options.resume = true,
+                "--stereo" => {
+                    let name = value()?;
+                    stereo_layout = Some(StereoLayout::parse(&name).ok_or_else(|| format!("unknown stereo layout {}", name))?);
+                }
+                "--ipd" => ipd = parse_number(&value()?)?,
+                "--convergence" => {
+                    convergence = parse_number(&value()?)?;
+                    if !(convergence > 0.0 && convergence.is_finite()) {
+                        return Err(format!("convergence distance must be positive, got {}", convergence));
+                    }
+                }
+                "--shutter" => {
+                    let interval = value()?;
+                    options.shutter = match interval.split(',').map(parse_number).collect::<Result<Vec<Float>, String>>()?[..] {
+                        [open, close] if open <= close => (open, close),
+                        _ => return Err(format!("expected open,close with open <= close, got {}", interval)),
+                    };
+                }
+                "--frames" => {
+                    let range = value()?;
+                    let (first, last) = range.split_once('-').unwrap_or((&range, &range));
+                    let (first, last) = (parse_integer(first)?, parse_integer(last)?);
+                    if first > last {
+                        return Err(format!("frame range must not end before it starts, got {}", range));
+                    }
+                    options.frames = Some((first, last));
+                }
+                "--fps" => {
+                    let fps = parse_number(&value()?)?;
+                    if !(fps > 0.0 && fps.is_finite()) {
+                        return Err(format!("frames per second must be positive, got {}", fps));
+                    }
+                    options.fps = fps;
+                }
+                "--filter" => {
+                    let name = value()?;
+                    let kind = FilterKind::parse(&name).ok_or_else(|| format!("unknown filter {}", name))?;
+                    filter_kind = kind;
+                }
+                "--filter-radius" => {
+                    let radius = parse_number(&value()?)?;
+                    if !(radius > 0.0 && radius.is_finite()) {
+                        return Err(format!("filter radius must be positive, got {}", radius));
+                    }
+                    filter_radius = Some(radius);
+                }
+                "--smoke" => options.smoke = true,
+                "--lamp" => options.lamp = true,
+                "--no-progress" => options.progress = false,
+                "--stats" => options.stats = true,
+                "--volume" => options.volume = Some(value()?),
+                "--volume-temperature" => options.volume_temperature = Some(value()?),
+                "--volume-density" => options.volume_density = parse_number(&value()?)?,
+                "--volume-emission" => options.volume_emission = parse_color(&value()?)?,
+                "-h" | "--help" => return Err(String::new()),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+
+        if let Some(layout) = stereo_layout {
+            // the viewer window only fits one eye
+            if options.output.is_none() {
+                return Err("--stereo needs --output".to_string());
+            }
+            options.stereo = Some(Stereo { ipd, convergence, layout });
+        }
+
+        if (options.checkpoint.is_some() || options.resume) && options.output.is_none() {
+            return Err("--checkpoint and --resume need --output".to_string());
+        }
+        if options.resume && options.checkpoint.is_none() {
+            return Err("--resume needs --checkpoint".to_string());
+        }
+
+        if let IntegratorKind::PhotonMapping { photons: count, radius } = &mut options.integrator {
+            *count = photons.unwrap_or(*count);
+            *radius = gather_radius.unwrap_or(*radius);
+        } else if photons.is_some() || gather_radius.is_some() {
+            return Err("--photons and --gather-radius need --integrator ppm".to_string());
+        }
+        if let IntegratorKind::Metropolis(settings) = &mut options.integrator {
+            settings.chains = chains.unwrap_or(settings.chains);
+            settings.large_step_probability = large_step.unwrap_or(settings.large_step_probability);
+            // the chains and their sample vectors are not part of a checkpoint
+            if options.checkpoint.is_some() {
+                return Err("--checkpoint does not work with Metropolis light transport".to_string());
+            }
+            // the image is all splats, no pixel has first hits or work of its own to report
+            if !options.aovs.is_empty() || options.cryptomatte_ranks > 0 || !options.heatmaps.is_empty() {
+                return Err("--aovs, --cryptomatte and --heatmap do not work with Metropolis light transport".to_string());
+            }
+        } else if chains.is_some() || large_step.is_some() {
+            return Err("--chains and --large-step need --integrator mlt or mlt-bdpt".to_string());
+        }
+
+        if options.spectral {
+            options.dispersion = dispersion.unwrap_or(options.dispersion);
+            let path = match options.integrator {
+                IntegratorKind::Path => true,
+                IntegratorKind::Metropolis(settings) => !settings.bidirectional,
+                _ => false,
+            };
+            if !path {
+                return Err("--spectral needs --integrator path or mlt".to_string());
+            }
+        } else if dispersion.is_some() {
+            return Err("--dispersion needs --spectral".to_string());
+        }
+
+        // only the path tracer starts from packet first hits, and heatmaps time every
+        // pixel on its own
+        if options.packet_size > 1 {
+            if options.integrator != IntegratorKind::Path {
+                return Err("--packets needs --integrator path".to_string());
+            }
+            if !options.heatmaps.is_empty() {
+                return Err("--packets does not work with --heatmap".to_string());
+            }
+        }
+
+        options.filter = Filter::new(filter_kind, filter_radius.unwrap_or(filter_kind.default_radius()));
+
+        // a time budget without a sample count keeps sampling until the time is up
+        let default_samples = if options.time_limit.is_some() { u32::MAX } else { 1 };
+        options.samples_per_pixel = samples_per_pixel.unwrap_or(default_samples);
+
+        Ok(options)
+    }
+}
+
+fn parse_number(value: &str) -> Result<Float, String> {
+    value.parse().map_err(|_| format!("expected a number, got {}", value))
+}
+
+fn parse_integer<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("expected an integer, got {}", value))
+}
+
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, String> {
+    if value == "all" {
+        return Ok(ALL_AOVS.to_vec());
+    }
+    value.split(',').map(|name| Aov::parse(name).ok_or_else(|| format!("unknown AOV {}", name))).collect()
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    let parts = value.split(',').map(parse_number).collect::<Result<Vec<Float>, String>>()?;
+    match parts[..] {
+        [r, g, b] => Ok(Color::new(r, g, b)),
+        _ => Err(format!("expected r,g,b, got {}", value)),
+    }
+}
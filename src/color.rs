@@ -0,0 +1,107 @@
+use std::ops::{Add, Div, Mul, Sub};
+use crate::float::Float;
+
+// Linear RGB radiance or reflectance, kept apart from positions and directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0.0, g: 0.0, b: 0.0 };
+    pub const WHITE: Color = Color { r: 1.0, g: 1.0, b: 1.0 };
+
+    pub fn new(r: Float, g: Float, b: Float) -> Color {
+        Color { r, g, b }
+    }
+
+    pub fn gray(value: Float) -> Color {
+        Color::new(value, value, value)
+    }
+
+    // Rec. 709 luminance.
+    pub fn luminance(self) -> Float {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn max_component(self) -> Float {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn near_zero(self) -> bool {
+        self.r.abs() < Float::EPSILON && self.g.abs() < Float::EPSILON && self.b.abs() < Float::EPSILON
+    }
+
+    pub fn clamp(self, min: Float, max: Float) -> Color {
+        Color::new(self.r.clamp(min, max), self.g.clamp(min, max), self.b.clamp(min, max))
+    }
+
+    pub fn map(self, f: impl Fn(Float) -> Float) -> Color {
+        Color::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    // Bytes of a display value in [0, 1], anything outside is clipped.
+    pub fn to_bytes(self) -> [u8; 3] {
+        let c = self.clamp(0.0, 1.0);
+        [(255.999 * c.r) as u8, (255.999 * c.g) as u8, (255.999 * c.b) as u8]
+    }
+
+    pub fn to_array(self) -> [Float; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    pub fn from_array(rgb: [Float; 3]) -> Color {
+        Color::new(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+impl Add for Color {
+    type Output = Color;
+
+    fn add(self, rhs: Color) -> Color {
+        Color::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
+    }
+}
+
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, rhs: Color) -> Color {
+        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+
+// Filters one color by another, channel by channel.
+impl Mul for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: Color) -> Color {
+        Color::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
+    }
+}
+
+impl Mul<Float> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: Float) -> Color {
+        Color::new(self.r * rhs, self.g * rhs, self.b * rhs)
+    }
+}
+
+impl Mul<Color> for Float {
+    type Output = Color;
+
+    fn mul(self, rhs: Color) -> Color {
+        rhs * self
+    }
+}
+
+impl Div<Float> for Color {
+    type Output = Color;
+
+    fn div(self, rhs: Float) -> Color {
+        Color::new(self.r / rhs, self.g / rhs, self.b / rhs)
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use crate::film::join_rows;
+
+// Object id derived from its name the way Cryptomatte does it, MurmurHash3 of the
+// name with the float exponent kept away from denormals, infinity and NaN so the
+// id survives being stored as a float.
+pub fn id_from_name(name: &str) -> u32 {
+    to_float_safe(murmur3_32(name.as_bytes(), 0))
+}
+
+pub fn to_float_safe(hash: u32) -> u32 {
+    let exponent = (hash >> 23) & 0xff;
+    if exponent == 0 || exponent == 0xff {
+        hash ^ (1 << 23)
+    } else {
+        hash
+    }
+}
+
+fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let mut h = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h ^= k;
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k |= (*byte as u32) << (8 * i);
+        }
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+// How much of every pixel each id covers, counted in samples. Id 0 is the
+// background and is left out like in Cryptomatte.
+#[derive(Debug)]
+pub struct CoverageLayer {
+    pub name: &'static str,
+    pub pixels: Vec<Vec<(u32, f32)>>,
+}
+
+impl CoverageLayer {
+    pub fn new(name: &'static str, pixel_count: usize) -> CoverageLayer {
+        CoverageLayer { name, pixels: vec![vec![]; pixel_count] }
+    }
+
+    pub fn join(&self, other: &CoverageLayer, width: usize, side_by_side: bool) -> CoverageLayer {
+        CoverageLayer { name: self.name, pixels: join_rows(&self.pixels, &other.pixels, width, side_by_side) }
+    }
+
+    pub fn add(&mut self, pixel: usize, id: u32) {
+        if id == 0 {
+            return;
+        }
+        let ids = &mut self.pixels[pixel];
+        match ids.iter_mut().find(|(other, _)| *other == id) {
+            Some((_, count)) => *count += 1.0,
+            None => ids.push((id, 1.0)),
+        }
+    }
+
+    // The ids of a pixel, most coverage first, with the share of its `samples` each
+    // one covers.
+    fn ranked(&self, pixel: usize, samples: u32) -> Vec<(u32, f32)> {
+        let mut ids = self.pixels[pixel].clone();
+        ids.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ids.iter().map(|(id, count)| (*id, count / samples.max(1) as f32)).collect()
+    }
+
+    // Writes the `ranks` ids with the most coverage of every pixel, one float map per
+    // rank with the id bits in red and the coverage in green, to
+    // `<base>.crypto_<layer>.rank<n>.pfm`, and a manifest of the known names.
+    pub fn write(&self, base: &str, ranks: usize, width: u32, height: u32, sample_counts: &[u32], names: &[String]) -> io::Result<()> {
+        let sorted: Vec<Vec<(u32, f32)>> = (0..self.pixels.len()).zip(sample_counts).map(|(pixel, samples)| self.ranked(pixel, *samples)).collect();
+
+        for rank in 0..ranks {
+            let mut contents = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+            for ids in &sorted {
+                let (id, coverage) = ids.get(rank).copied().unwrap_or((0, 0.0));
+                for v in [f32::from_bits(id), coverage, 0.0] {
+                    contents.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            fs::write(format!("{}.crypto_{}.rank{}.pfm", base, self.name, rank), contents)?;
+        }
+
+        // ids nobody gave a name to show up under their hash
+        let mut manifest: BTreeMap<String, u32> = names.iter().map(|name| (name.clone(), id_from_name(name))).collect();
+        let named: BTreeSet<u32> = manifest.values().copied().collect();
+        let seen: BTreeSet<u32> = self.pixels.iter().flatten().map(|(id, _)| *id).collect();
+        for id in seen.difference(&named) {
+            manifest.insert(format!("{}_{:08x}", self.name, id), *id);
+        }
+        let entries: Vec<String> = manifest.iter()
+            .map(|(name, id)| format!("\"{}\":\"{:08x}\"", name.replace('\\', "\\\\").replace('"', "\\\""), id))
+            .collect();
+        fs::write(format!("{}.crypto_{}.json", base, self.name), format!("{{{}}}\n", entries.join(",")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_murmur3_hashes() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"hello", 0), 0x248bfa47);
+        assert_eq!(id_from_name("The quick brown fox jumps over the lazy dog"), 0x2e4ff723);
+        // exponents of zero and all ones get a bit flipped
+        assert_eq!(to_float_safe(0x0000_1234), 0x0080_1234);
+        assert_eq!(to_float_safe(0x7f80_0001), 0x7f00_0001);
+    }
+
+    #[test]
+    fn objects_sharing_a_pixel_split_its_coverage() {
+        let (ball, ground) = (id_from_name("ball"), id_from_name("ground"));
+        let mut layer = CoverageLayer::new("object", 2);
+        for id in [ball, ground, ball, ball] {
+            layer.add(1, id);
+        }
+        let ranked = layer.ranked(1, 4);
+        assert_eq!(ranked, vec![(ball, 0.75), (ground, 0.25)]);
+        assert_eq!(ranked.iter().map(|(_, coverage)| coverage).sum::<f32>(), 1.0);
+        // the background takes up the rest of a pixel but is not ranked
+        layer.add(0, 0);
+        assert!(layer.ranked(0, 1).is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::color::Color;
+use crate::film::{Features, Film};
+use crate::float::Float;
+
+const ITERATIONS: u32 = 5;
+const KERNEL: [Float; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const SIGMA_LUMINANCE: Float = 4.0;
+const SIGMA_DEPTH: Float = 0.05;
+const SIGMA_ALBEDO: Float = 0.1;
+const NORMAL_POWER: i32 = 64;
+const ALBEDO_EPSILON: Float = 0.01;
+// below this many samples the per pixel variance is too unreliable, the
+// variance of the neighbourhood is used instead
+const MIN_VARIANCE_SAMPLES: u32 = 16;
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) guided by the first hit
+// albedo, normal and depth the film recorded. The albedo is divided out before
+// filtering and multiplied back in afterwards, so surface colour stays sharp and
+// only the lighting gets smoothed. Like SVGF, the colour edge-stopping function is
+// scaled by the luminance variance of each pixel, which is filtered along with it.
+pub fn denoise(pixels: &[Vec<Color>], film: &Film) -> Vec<Vec<Color>> {
+    let width = film.width as usize;
+    let height = film.height as usize;
+    if width == 0 || pixels.len() != height {
+        return pixels.to_vec();
+    }
+
+    let features: Vec<Features> = film.pixels.iter().map(|p| {
+        let mut features = p.features();
+        if features.normal.length_squared() > 0.0 {
+            features.normal = features.normal.normalize();
+        }
+        features
+    }).collect();
+    let mut lighting: Vec<Color> = pixels.iter().flatten().zip(&features)
+        .map(|(color, f)| demodulate(*color, f.albedo))
+        .collect();
+    let mut variance = initial_variance(film, &features, &lighting, width, height);
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let mut filtered = vec![Color::BLACK; lighting.len()];
+        let mut filtered_variance = vec![0.0; lighting.len()];
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let luminance_p = lighting[p].luminance();
+                let sigma = SIGMA_LUMINANCE * variance[p].sqrt() + 1e-4;
+                let mut sum = Color::BLACK;
+                let mut variance_sum = 0.0;
+                let mut weight_sum = 0.0;
+                for dy in -2..=2isize {
+                    for dx in -2..=2isize {
+                        let qx = x as isize + dx * step;
+                        let qy = y as isize + dy * step;
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        let kernel = KERNEL[dx.unsigned_abs()] * KERNEL[dy.unsigned_abs()];
+                        let color = (-(luminance_p - lighting[q].luminance()).abs() / sigma).exp();
+                        let weight = kernel * color * feature_weight(&features[p], &features[q]);
+                        sum = sum + weight * lighting[q];
+                        variance_sum += weight * weight * variance[q];
+                        weight_sum += weight;
+                    }
+                }
+                filtered[p] = sum / weight_sum;
+                filtered_variance[p] = variance_sum / (weight_sum * weight_sum);
+            }
+        }
+        lighting = filtered;
+        variance = filtered_variance;
+    }
+
+    lighting.chunks(width)
+        .zip(features.chunks(width))
+        .map(|(row, features)| row.iter().zip(features).map(|(l, f)| remodulate(*l, f.albedo)).collect())
+        .collect()
+}
+
+// Luminance variance of the demodulated lighting of every pixel.
+fn initial_variance(film: &Film, features: &[Features], lighting: &[Color], width: usize, height: usize) -> Vec<Float> {
+    let mut variance = vec![0.0; lighting.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let p = y * width + x;
+            let pixel = &film.pixels[p];
+            if pixel.sample_count >= MIN_VARIANCE_SAMPLES {
+                let albedo = features[p].albedo.luminance() + ALBEDO_EPSILON;
+                let sample_variance = pixel.m2 / (pixel.sample_count - 1) as Float;
+                variance[p] = sample_variance / pixel.sample_count as Float / (albedo * albedo);
+                continue;
+            }
+
+            let mut sum = 0.0;
+            let mut sum_squared = 0.0;
+            let mut weight_sum = 0.0;
+            for qy in y.saturating_sub(2)..(y + 3).min(height) {
+                for qx in x.saturating_sub(2)..(x + 3).min(width) {
+                    let q = qy * width + qx;
+                    let weight = feature_weight(&features[p], &features[q]);
+                    let l = lighting[q].luminance();
+                    sum += weight * l;
+                    sum_squared += weight * l * l;
+                    weight_sum += weight;
+                }
+            }
+            let mean = sum / weight_sum;
+            variance[p] = (sum_squared / weight_sum - mean * mean).max(0.0);
+        }
+    }
+    variance
+}
+
+fn feature_weight(p: &Features, q: &Features) -> Float {
+    let difference = p.albedo - q.albedo;
+    let distance_squared = difference.r * difference.r + difference.g * difference.g + difference.b * difference.b;
+    let albedo = (-distance_squared / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp();
+    let depth = (-(p.depth - q.depth).abs() / (SIGMA_DEPTH * p.depth.max(1e-3))).exp();
+    let normal = if p.normal.length_squared() == 0.0 || q.normal.length_squared() == 0.0 {
+        // background only blends with background
+        if p.normal.length_squared() == q.normal.length_squared() { 1.0 } else { 0.0 }
+    } else {
+        p.normal.dot(&q.normal).max(0.0).powi(NORMAL_POWER)
+    };
+    albedo * depth * normal
+}
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    Color::new(
+        color.r / (albedo.r + ALBEDO_EPSILON),
+        color.g / (albedo.g + ALBEDO_EPSILON),
+        color.b / (albedo.b + ALBEDO_EPSILON),
+    )
+}
+
+fn remodulate(lighting: Color, albedo: Color) -> Color {
+    lighting * (albedo + Color::gray(ALBEDO_EPSILON))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    const WIDTH: u32 = 16;
+    const HEIGHT: u32 = 4;
+
+    // An image whose left and right halves have their own color and first hit, each
+    // pixel seen by four samples of its color.
+    fn halves(colors: [Color; 2], albedos: [Color; 2], normals: [Vec3; 2]) -> (Vec<Vec<Color>>, Film) {
+        let mut film = Film::new(WIDTH, HEIGHT, &[], false);
+        let mut pixels = vec![vec![Color::BLACK; WIDTH as usize]; HEIGHT as usize];
+        for j in 0..HEIGHT {
+            for i in 0..WIDTH {
+                let side = (i >= WIDTH / 2) as usize;
+                let features = Features { albedo: albedos[side], normal: normals[side], depth: 1.0, ..Features::default() };
+                for _ in 0..4 {
+                    film.pixel_mut(i, j).add_sample(colors[side], 1.0, &features);
+                }
+                pixels[j as usize][i as usize] = colors[side];
+            }
+        }
+        (pixels, film)
+    }
+
+    fn largest_change(before: &[Vec<Color>], after: &[Vec<Color>]) -> Float {
+        before.iter().flatten().zip(after.iter().flatten())
+            .map(|(a, b)| {
+                let difference = *a - *b;
+                difference.r.abs().max(difference.g.abs()).max(difference.b.abs())
+            })
+            .fold(0.0, Float::max)
+    }
+
+    #[test]
+    fn constant_image_is_unchanged() {
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let (pixels, film) = halves([Color::gray(0.4); 2], [Color::gray(0.5); 2], [up; 2]);
+        assert!(largest_change(&pixels, &denoise(&pixels, &film)) < 1e-4);
+    }
+
+    #[test]
+    fn edges_in_the_features_are_not_blurred_across() {
+        let colors = [Color::gray(0.2), Color::gray(0.8)];
+        let gray = [Color::gray(0.5); 2];
+        let up = Vec3::new(0.0, 0.0, 1.0);
+
+        // the same first hit everywhere, the color step gets smoothed
+        let (pixels, film) = halves(colors, gray, [up; 2]);
+        assert!(largest_change(&pixels, &denoise(&pixels, &film)) > 0.05);
+
+        let (pixels, film) = halves(colors, gray, [up, Vec3::new(1.0, 0.0, 0.0)]);
+        assert!(largest_change(&pixels, &denoise(&pixels, &film)) < 1e-4);
+
+        let (pixels, film) = halves(colors, [Color::gray(0.2), Color::gray(0.9)], [up; 2]);
+        assert!(largest_change(&pixels, &denoise(&pixels, &film)) < 1e-4);
+    }
+}
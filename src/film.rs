@@ -0,0 +1,343 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::aov::{Aov, AovBuffer};
+use crate::cryptomatte::CoverageLayer;
+use crate::float::{to_f64, Float};
+use crate::heatmap::HeatmapBuffer;
+use crate::hittable::HitRecord;
+use crate::projection::StereoLayout;
+use crate::ray::Ray;
+use crate::color::Color;
+use crate::vec3::Vec3;
+
+// First hit surface properties of a camera sample, used to guide the denoiser,
+// and the split of its light into the AOV passes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Features {
+    pub albedo: Color,
+    // a plain vector as pixels average it over their samples
+    pub normal: Vec3,
+    pub depth: Float,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub emission: Color,
+    pub diffuse_direct: Color,
+    pub diffuse_indirect: Color,
+    pub specular: Color,
+    // light emitted at the second vertex of the path, what makes up the direct pass
+    pub first_bounce_light: Color,
+    // rays the sample traced
+    pub rays: u32,
+}
+
+impl Features {
+    // What the camera sees of the first hit of `ray`.
+    pub fn first_hit(ray: &Ray, hit_record: &HitRecord) -> Features {
+        Features {
+            albedo: hit_record.material.map_or(Color::BLACK, |m| m.albedo()),
+            normal: hit_record.normal.to_vec(),
+            depth: hit_record.t * ray.direction.length(),
+            object_id: hit_record.object_id,
+            material_id: hit_record.material.map_or(0, |m| m.id()),
+            emission: hit_record.material.map_or(Color::BLACK, |m| m.emitted()),
+            ..Features::default()
+        }
+    }
+}
+
+// Accumulated samples of one pixel. Besides the filter weighted color sum it keeps
+// a running mean and variance of the sample luminance (Welford's algorithm) for
+// adaptive sampling.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FilmPixel {
+    pub weighted_sum: Color,
+    pub unweighted_sum: Color,
+    pub weight_sum: Float,
+    pub albedo_sum: Color,
+    pub normal_sum: Vec3,
+    pub depth_sum: Float,
+    pub sample_count: u32,
+    pub mean: Float,
+    pub m2: Float,
+    pub converged: bool,
+}
+
+impl FilmPixel {
+    pub fn add_sample(&mut self, color: Color, weight: Float, features: &Features) {
+        self.weighted_sum = self.weighted_sum + weight * color;
+        self.unweighted_sum = self.unweighted_sum + color;
+        self.weight_sum += weight;
+        self.albedo_sum = self.albedo_sum + features.albedo;
+        self.normal_sum = self.normal_sum + features.normal;
+        self.depth_sum += features.depth;
+
+        let value = color.luminance();
+        self.sample_count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.sample_count as Float;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Standard error of the mean, relative to the square root of the pixel brightness
+    // so that dark pixels are not held to a stricter standard than the eye can see.
+    pub fn relative_error(&self) -> Float {
+        if self.sample_count < 2 {
+            return Float::INFINITY;
+        }
+        let variance = self.m2 / (self.sample_count - 1) as Float;
+        (variance / self.sample_count as Float).sqrt() / self.mean.max(1e-4).sqrt()
+    }
+
+    // Adaptive sampling stops at `threshold`, but never before `min_samples`, nor
+    // before there are two samples to measure the error from.
+    pub fn has_converged(&self, threshold: Float, min_samples: u32) -> bool {
+        threshold > 0.0 && self.sample_count >= min_samples.max(2) && self.relative_error() < threshold
+    }
+
+    pub fn color(&self) -> Color {
+        if self.weight_sum > 0.0 {
+            self.weighted_sum / self.weight_sum
+        } else if self.sample_count > 0 {
+            // negative filter lobes can cancel out the weights of a handful of samples
+            self.unweighted_sum / self.sample_count as Float
+        } else {
+            Color::BLACK
+        }
+    }
+
+    pub fn features(&self) -> Features {
+        let n = self.sample_count.max(1) as Float;
+        Features {
+            albedo: self.albedo_sum / n,
+            normal: self.normal_sum / n,
+            depth: self.depth_sum / n,
+            ..Features::default()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<FilmPixel>,
+    pub aovs: Vec<AovBuffer>,
+    pub cryptomatte: Vec<CoverageLayer>,
+    pub heatmaps: Vec<HeatmapBuffer>,
+    // light tracing contributions, empty unless the integrator splats
+    pub splats: Vec<Color>,
+    // light paths traced for the splats, every pixel gets pixel count / light paths
+    // of its splat sum
+    pub light_paths: u64,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, aovs: &[Aov], cryptomatte: bool) -> Film {
+        let pixel_count = (width * height) as usize;
+        let layers = if cryptomatte { vec!["object", "material"] } else { vec![] };
+        Film {
+            width,
+            height,
+            pixels: vec![FilmPixel::default(); pixel_count],
+            aovs: aovs.iter().map(|aov| AovBuffer::new(*aov, pixel_count)).collect(),
+            cryptomatte: layers.into_iter().map(|name| CoverageLayer::new(name, pixel_count)).collect(),
+            heatmaps: vec![],
+            splats: vec![],
+            light_paths: 0,
+        }
+    }
+
+    // Puts the views of both eyes into one film.
+    pub fn join(left: Film, right: Film, layout: StereoLayout) -> Film {
+        let side_by_side = layout == StereoLayout::SideBySide;
+        let width = left.width as usize;
+        // the eyes may have traced different numbers of light paths, bring their
+        // splats to the shared scale of the joined film
+        let light_paths = left.light_paths + right.light_paths;
+        let rescale = |film: &Film| -> Vec<Color> {
+            let scale = light_paths as Float / (2.0 * film.light_paths.max(1) as Float);
+            match film.splats.len() {
+                0 => vec![Color::BLACK; film.pixels.len()],
+                _ => film.splats.iter().map(|s| *s * scale).collect(),
+            }
+        };
+        let splats = match light_paths {
+            0 => vec![],
+            _ => join_rows(&rescale(&left), &rescale(&right), width, side_by_side),
+        };
+        Film {
+            width: if side_by_side { left.width * 2 } else { left.width },
+            height: if side_by_side { left.height } else { left.height * 2 },
+            pixels: join_rows(&left.pixels, &right.pixels, width, side_by_side),
+            aovs: left.aovs.iter().zip(&right.aovs).map(|(l, r)| AovBuffer {
+                aov: l.aov,
+                data: join_rows(&l.data, &r.data, width * l.aov.channels(), side_by_side),
+            }).collect(),
+            cryptomatte: left.cryptomatte.iter().zip(&right.cryptomatte)
+                .map(|(l, r)| l.join(r, width, side_by_side))
+                .collect(),
+            heatmaps: left.heatmaps.iter().zip(&right.heatmaps)
+                .map(|(l, r)| l.join(r, width, side_by_side))
+                .collect(),
+            splats,
+            light_paths,
+        }
+    }
+
+    pub fn pixel(&self, i: u32, j: u32) -> &FilmPixel {
+        &self.pixels[(j * self.width + i) as usize]
+    }
+
+    pub fn pixel_mut(&mut self, i: u32, j: u32) -> &mut FilmPixel {
+        &mut self.pixels[(j * self.width + i) as usize]
+    }
+
+    // Call after the sample was added to the pixel, ids are taken from its first sample.
+    pub fn add_passes(&mut self, i: u32, j: u32, features: &Features) {
+        let index = (j * self.width + i) as usize;
+        let sample_count = self.pixels[index].sample_count;
+        for buffer in &mut self.aovs {
+            buffer.add(index, features, sample_count);
+        }
+        for layer in &mut self.cryptomatte {
+            let id = if layer.name == "object" { features.object_id } else { features.material_id };
+            layer.add(index, id);
+        }
+    }
+
+    // Writes every pass to `<base>.<pass name>.pfm`.
+    pub fn write_aovs(&self, base: &str) -> io::Result<()> {
+        let sample_counts: Vec<u32> = self.pixels.iter().map(|p| p.sample_count).collect();
+        for buffer in &self.aovs {
+            let path = format!("{}.{}.pfm", base, buffer.aov.name());
+            buffer.write_pfm(&path, self.width, self.height, &sample_counts)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_cryptomatte(&self, base: &str, ranks: usize, object_names: &[String]) -> io::Result<()> {
+        let sample_counts: Vec<u32> = self.pixels.iter().map(|p| p.sample_count).collect();
+        for layer in &self.cryptomatte {
+            let names = if layer.name == "object" { object_names } else { &[] };
+            layer.write(base, ranks, self.width, self.height, &sample_counts, names)?;
+        }
+        Ok(())
+    }
+
+    fn map<T>(&self, f: impl Fn(&FilmPixel) -> T) -> Vec<Vec<T>> {
+        (0..self.height)
+            .map(|j| (0..self.width).map(|i| f(self.pixel(i, j))).collect())
+            .collect()
+    }
+
+    pub fn image(&self) -> Vec<Vec<Color>> {
+        if self.splats.is_empty() || self.light_paths == 0 {
+            return self.map(|pixel| pixel.color());
+        }
+        let scale = self.pixels.len() as Float / self.light_paths as Float;
+        (0..self.height)
+            .map(|j| (0..self.width).map(|i| {
+                let index = (j * self.width + i) as usize;
+                self.pixels[index].color() + self.splats[index] * scale
+            }).collect())
+            .collect()
+    }
+
+    // Debug view, brighter pixels received more samples.
+    pub fn sample_count_image(&self) -> Vec<Vec<Color>> {
+        let max = self.pixels.iter().map(|p| p.sample_count).max().unwrap_or(0).max(1) as Float;
+        self.map(|pixel| {
+            Color::gray(pixel.sample_count as Float / max)
+        })
+    }
+}
+
+// Collects light tracing contributions from any number of threads at once, they
+// land on arbitrary pixels rather than the one being rendered.
+#[derive(Debug)]
+pub struct SplatFilm {
+    width: u32,
+    height: u32,
+    // f64 bits of the red, green and blue sums
+    pixels: Vec<[AtomicU64; 3]>,
+    paths: AtomicU64,
+}
+
+impl SplatFilm {
+    pub fn new(width: u32, height: u32) -> SplatFilm {
+        let pixels = (0..width * height).map(|_| [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)]).collect();
+        SplatFilm { width, height, pixels, paths: AtomicU64::new(0) }
+    }
+
+    pub fn add(&self, i: u32, j: u32, color: Color) {
+        if i >= self.width || j >= self.height {
+            return;
+        }
+        let pixel = &self.pixels[(j * self.width + i) as usize];
+        for (sum, value) in pixel.iter().zip(color.to_array()) {
+            if value != 0.0 {
+                let _ = sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + to_f64(value)).to_bits()));
+            }
+        }
+    }
+
+    // Counts one light path towards the normalization of the splats.
+    pub fn add_path(&self) {
+        self.paths.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Moves everything splatted so far into `film`.
+    pub fn drain_into(&self, film: &mut Film) {
+        if film.splats.is_empty() {
+            film.splats = vec![Color::BLACK; film.pixels.len()];
+        }
+        for (splat, pixel) in film.splats.iter_mut().zip(&self.pixels) {
+            let sums = pixel.each_ref().map(|sum| f64::from_bits(sum.swap(0, Ordering::Relaxed)) as Float);
+            *splat = *splat + Color::from_array(sums);
+        }
+        film.light_paths += self.paths.swap(0, Ordering::Relaxed);
+    }
+}
+
+// Joins two images stored bottom row first with `row_len` values per row, `first`
+// goes left of or above `second`.
+pub fn join_rows<T: Clone>(first: &[T], second: &[T], row_len: usize, side_by_side: bool) -> Vec<T> {
+    if side_by_side {
+        first.chunks(row_len).zip(second.chunks(row_len)).flat_map(|(a, b)| a.iter().chain(b)).cloned().collect()
+    } else {
+        second.iter().chain(first).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(values: &[Float]) -> FilmPixel {
+        let mut pixel = FilmPixel::default();
+        for &value in values {
+            pixel.add_sample(Color::new(value, value, value), 1.0, &Features::default());
+        }
+        pixel
+    }
+
+    #[test]
+    fn relative_error_needs_two_samples() {
+        assert_eq!(pixel(&[]).relative_error(), Float::INFINITY);
+        assert_eq!(pixel(&[0.5]).relative_error(), Float::INFINITY);
+        assert_eq!(pixel(&[0.5; 4]).relative_error(), 0.0);
+        assert!(pixel(&[0.2, 0.8]).relative_error() > 0.0);
+    }
+
+    #[test]
+    fn convergence_waits_for_min_samples() {
+        let constant = pixel(&[0.5; 4]);
+        assert!(constant.has_converged(0.01, 4));
+        assert!(!constant.has_converged(0.01, 5));
+        // two samples are the least an error can be measured from
+        assert!(!pixel(&[0.5]).has_converged(0.01, 0));
+        assert!(pixel(&[0.5; 2]).has_converged(0.01, 0));
+        // a threshold of zero turns adaptive sampling off
+        assert!(!constant.has_converged(0.0, 0));
+    }
+}
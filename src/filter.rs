@@ -0,0 +1,135 @@
+use crate::float::Float;
+use crate::float::consts::PI;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FilterKind {
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn parse(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn default_radius(&self) -> Float {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+// Separable pixel reconstruction filter, radius is in pixels and may extend past
+// the pixel footprint.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: Float,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: Float) -> Filter {
+        Filter { kind, radius }
+    }
+
+    pub fn evaluate_1d(&self, x: Float) -> Float {
+        let r = self.radius;
+        if x.abs() > r {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x.abs(),
+            FilterKind::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |x: Float| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell_1d(2.0 * x / r, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+fn mitchell_1d(x: Float, b: Float, c: Float) -> Float {
+    let x = x.abs();
+    if x <= 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    } else if x <= 2.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+const TABLE_SIZE: usize = 256;
+
+// Filter importance sampling: pixel offsets are drawn proportionally to |f| along
+// each axis, and the returned weight f / pdf carries the sign of the negative lobes.
+// Pixels are the weighted average sum(w * L) / sum(w) of their samples.
+pub struct FilterSampler {
+    filter: Filter,
+    cdf: Vec<Float>,
+    integral: Float,
+}
+
+impl FilterSampler {
+    pub fn new(filter: Filter) -> FilterSampler {
+        let mut cdf = Vec::with_capacity(TABLE_SIZE + 1);
+        cdf.push(0.0);
+        let dx = 2.0 * filter.radius / TABLE_SIZE as Float;
+        let mut sum = 0.0;
+        for i in 0..TABLE_SIZE {
+            let x = -filter.radius + (i as Float + 0.5) * dx;
+            sum += filter.evaluate_1d(x).abs() * dx;
+            cdf.push(sum);
+        }
+        for value in cdf.iter_mut() {
+            *value /= sum;
+        }
+        FilterSampler { filter, cdf, integral: sum }
+    }
+
+    // Returns the pixel offset for a 2D sample and the weight of the resulting radiance sample.
+    pub fn sample(&self, u: (Float, Float)) -> (Float, Float, Float) {
+        if self.filter.kind == FilterKind::Box {
+            return ((2.0 * u.0 - 1.0) * self.filter.radius, (2.0 * u.1 - 1.0) * self.filter.radius, 1.0);
+        }
+        let (x, pdf_x) = self.sample_1d(u.0);
+        let (y, pdf_y) = self.sample_1d(u.1);
+        let weight = self.filter.evaluate_1d(x) * self.filter.evaluate_1d(y) / (pdf_x * pdf_y);
+        // scaled so positive filters give every sample unit weight, like the box filter
+        (x, y, weight / (self.integral * self.integral))
+    }
+
+    fn sample_1d(&self, u: Float) -> (Float, Float) {
+        let index = self.cdf.partition_point(|c| *c <= u).clamp(1, TABLE_SIZE) - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let t = if width > 0.0 { (u - self.cdf[index]) / width } else { 0.5 };
+        let dx = 2.0 * self.filter.radius / TABLE_SIZE as Float;
+        let x = -self.filter.radius + (index as Float + t) * dx;
+        (x, width / dx)
+    }
+}
@@ -0,0 +1,27 @@
+// Precision of the renderer's math. f64 by default, building with the `f32` feature
+// trades precision for speed and half the memory per vector.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+
+// Largest value below one, random numbers are clamped to it so they stay in [0, 1)
+// after rounding to Float.
+pub const ONE_MINUS_EPSILON: Float = 1.0 - Float::EPSILON / 2.0;
+
+// Conversions for file formats, hashes and durations that have a fixed precision.
+// One of them does nothing in either build.
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(v: Float) -> f32 {
+    v as f32
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f64(v: Float) -> f64 {
+    v as f64
+}
@@ -0,0 +1,178 @@
+use crate::film::join_rows;
+use crate::color::Color;
+use crate::float::Float;
+
+const LEGEND_BAR_HEIGHT: usize = 12;
+const LEGEND_TEXT_SCALE: usize = 2;
+// above the limit of the color scale only the hottest one percent of pixels is left
+const SCALE_PERCENTILE: Float = 0.99;
+
+// 3x5 pixel glyphs for the legend labels, rows from the top, three bits per row.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const POINT: [u8; 5] = [0b000, 0b000, 0b000, 0b000, 0b010];
+
+// Debug views that color pixels by how much work they took.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Heatmap {
+    // BVH nodes visited per sample
+    BvhNodes,
+    // sphere and volume intersection tests per sample
+    Tests,
+    // microseconds spent on the pixel over all its samples
+    Time,
+}
+
+impl Heatmap {
+    pub fn parse(name: &str) -> Option<Heatmap> {
+        match name {
+            "bvh" => Some(Heatmap::BvhNodes),
+            "tests" => Some(Heatmap::Tests),
+            "time" => Some(Heatmap::Time),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Heatmap::BvhNodes => "bvh",
+            Heatmap::Tests => "tests",
+            Heatmap::Time => "time",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Heatmap::BvhNodes => "BVH nodes per sample",
+            Heatmap::Tests => "intersection tests per sample",
+            Heatmap::Time => "microseconds per pixel",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HeatmapBuffer {
+    pub heatmap: Heatmap,
+    sums: Vec<Float>,
+    // samples taken by this render, checkpoints do not keep heatmaps
+    samples: Vec<u32>,
+}
+
+impl HeatmapBuffer {
+    pub fn new(heatmap: Heatmap, pixel_count: usize) -> HeatmapBuffer {
+        HeatmapBuffer { heatmap, sums: vec![0.0; pixel_count], samples: vec![0; pixel_count] }
+    }
+
+    pub fn join(&self, other: &HeatmapBuffer, row_len: usize, side_by_side: bool) -> HeatmapBuffer {
+        HeatmapBuffer {
+            heatmap: self.heatmap,
+            sums: join_rows(&self.sums, &other.sums, row_len, side_by_side),
+            samples: join_rows(&self.samples, &other.samples, row_len, side_by_side),
+        }
+    }
+
+    pub fn add(&mut self, pixel: usize, value: Float) {
+        self.sums[pixel] += value;
+        self.samples[pixel] += 1;
+    }
+
+    fn value(&self, pixel: usize) -> Float {
+        match self.heatmap {
+            Heatmap::Time => self.sums[pixel],
+            _ => self.sums[pixel] / self.samples[pixel].max(1) as Float,
+        }
+    }
+
+    // False color image with a legend below it, stored bottom row first, and the
+    // value the top of the color scale stands for.
+    pub fn image(&self, width: u32, height: u32) -> (Vec<Vec<Color>>, Float) {
+        let (width, height) = (width as usize, height as usize);
+        let mut values: Vec<Float> = (0..self.sums.len()).map(|pixel| self.value(pixel)).collect();
+        let mut sorted = values.clone();
+        sorted.sort_by(Float::total_cmp);
+        let top = sorted.get(((sorted.len() as Float - 1.0) * SCALE_PERCENTILE) as usize).copied().unwrap_or(0.0).max(1e-9);
+        values.iter_mut().for_each(|v| *v = (*v / top).min(1.0));
+
+        let mut rows = legend(width, top);
+        rows.extend((0..height).map(|j| (0..width).map(|i| false_color(values[j * width + i])).collect()));
+        (rows, top)
+    }
+}
+
+// Black through blue, magenta and orange to white.
+fn false_color(t: Float) -> Color {
+    const STOPS: [(Float, Float, Float); 5] = [(0.0, 0.0, 0.0), (0.1, 0.1, 0.6), (0.7, 0.1, 0.5), (1.0, 0.6, 0.0), (1.0, 1.0, 1.0)];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as Float;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let s = x - i as Float;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    Color::new(a.0 + (b.0 - a.0) * s, a.1 + (b.1 - a.1) * s, a.2 + (b.2 - a.2) * s)
+}
+
+// Color bar labelled with 0, half and the top of the scale at its left end,
+// middle and right end, rows bottom first.
+fn legend(width: usize, top: Float) -> Vec<Vec<Color>> {
+    let background = Color::BLACK;
+    let text_height = 5 * LEGEND_TEXT_SCALE;
+    // drawn top down, flipped at the end
+    let mut rows = vec![vec![background; width]; 2];
+    for _ in 0..LEGEND_BAR_HEIGHT {
+        rows.push((0..width).map(|i| false_color(i as Float / (width - 1).max(1) as Float)).collect());
+    }
+    rows.extend(vec![vec![background; width]; text_height + 4]);
+
+    let labels = [(0.0, 0.0), (0.5, top / 2.0), (1.0, top)];
+    for (position, value) in labels {
+        let text = format_value(value);
+        let text_width = text.len() * 4 * LEGEND_TEXT_SCALE;
+        let x = ((position * width as Float) as usize).saturating_sub((text_width as Float * position) as usize);
+        draw_text(&mut rows, x, LEGEND_BAR_HEIGHT + 4, &text);
+    }
+    rows.reverse();
+    rows
+}
+
+fn format_value(value: Float) -> String {
+    if value >= 100.0 || value == 0.0 {
+        format!("{:.0}", value)
+    } else if value >= 10.0 {
+        format!("{:.1}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn draw_text(rows: &mut [Vec<Color>], x: usize, y: usize, text: &str) {
+    let white = Color::WHITE;
+    for (n, c) in text.chars().enumerate() {
+        let glyph = match c.to_digit(10) {
+            Some(digit) => DIGITS[digit as usize],
+            None => POINT,
+        };
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..LEGEND_TEXT_SCALE {
+                    for dx in 0..LEGEND_TEXT_SCALE {
+                        let px = x + (n * 4 + column) * LEGEND_TEXT_SCALE + dx;
+                        if let Some(pixel) = rows[y + row * LEGEND_TEXT_SCALE + dy].get_mut(px) {
+                            *pixel = white;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,28 @@
+use crate::float::Float;
+use crate::float::consts::PI;
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::cryptomatte::id_from_name;
+use crate::libs::sample_unit_vector;
 use crate::material::{Material};
+use crate::packet::{Float4, RayChunk, RayPacket};
 use crate::ray::Ray;
-use crate::vec3::{Point3, Vec3};
+use crate::stats;
+use crate::color::Color;
+use crate::vec3::{Normal3, Point3, Vec3};
 
 #[derive(Clone, Copy)]
 pub struct HitRecord {
     pub point: Point3,
-    pub normal: Vec3,
-    pub t: f64,
+    pub normal: Normal3,
+    pub t: Float,
     pub front_face: bool,
-    pub material: Option<Material>
+    pub material: Option<Material>,
+    // surface coordinates in [0, 1]
+    pub u: Float,
+    pub v: Float,
+    // hash of the object name, or the position in its HittableList for unnamed objects
+    pub object_id: u32,
 }
 
 impl HitRecord {
@@ -16,39 +30,156 @@ impl HitRecord {
         HitRecord {
             t: 0.0,
             point: Point3::new(0.0, 0.0, 0.0),
-            normal: Point3::new(0.0, 0.0, 0.0),
+            normal: Normal3::new(0.0, 0.0, 0.0),
             front_face: false,
             material: None,
+            u: 0.0,
+            v: 0.0,
+            object_id: 0,
         }
     }
 
-    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) {
-        self.front_face = ray.direction.dot(&outward_normal) < 0.0;
+    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Normal3) {
+        self.front_face = outward_normal.dot(&ray.direction) < 0.0;
         self.normal = if self.front_face { outward_normal } else { -outward_normal };
     }
 }
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray, ray_t_min: f64, ray_t_max: f64, hit_record: &mut HitRecord) -> bool;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool;
+
+    // Bounds of the object over its whole motion.
+    fn bounding_box(&self) -> Aabb;
+
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    // The sphere this object is, packets of rays test spheres several rays at a time
+    // and everything else ray by ray.
+    fn sphere(&self) -> Option<&Sphere> {
+        None
+    }
+}
+
+// Gives an object a name and an id derived from it that stays the same when the
+// scene around it changes.
+pub struct Named {
+    name: String,
+    id: u32,
+    object: Box<dyn Hittable>,
+}
+
+impl Named {
+    pub fn new(name: &str, object: Box<dyn Hittable>) -> Named {
+        Named { name: name.to_string(), id: id_from_name(name), object }
+    }
+}
+
+impl Hittable for Named {
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool {
+        if !self.object.hit(ray, ray_t_min, ray_t_max, hit_record) {
+            return false;
+        }
+        hit_record.object_id = self.id;
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn sphere(&self) -> Option<&Sphere> {
+        self.object.sphere()
+    }
 }
 
 pub struct Sphere {
-    center: Vec3,
-    radius: f64,
+    center: Point3,
+    // how far the center moves between time 0 and 1, it holds still outside of that
+    motion: Vec3,
+    radius: Float,
     material: Option<Material>
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Option<Material>) -> Sphere {
+    pub fn new(center: Point3, radius: Float, material: Option<Material>) -> Sphere {
+        Sphere::moving(center, center, radius, material)
+    }
+
+    pub fn moving(start: Point3, end: Point3, radius: Float, material: Option<Material>) -> Sphere {
         Sphere {
-            center, radius, material
+            center: start, motion: end - start, radius, material
         }
     }
+
+    fn center_at(&self, time: Float) -> Point3 {
+        self.center + time.clamp(0.0, 1.0) * self.motion
+    }
+
+    // Fills in the hit `root` along the ray.
+    fn record(&self, ray: &Ray, root: Float, hit_record: &mut HitRecord) {
+        let center = self.center_at(ray.time);
+        hit_record.t = root;
+        hit_record.point = ray.at(hit_record.t);
+        hit_record.material = self.material;
+        let outward_normal = Normal3::from_vec((hit_record.point - center) / self.radius);
+        hit_record.set_face_normal(ray, outward_normal);
+        (hit_record.u, hit_record.v) = sphere_uv(outward_normal);
+    }
+
+    // `hit` for the rays of a chunk in `lanes`, with the same arithmetic four lanes at
+    // a time so every ray gets the hit it would get on its own.
+    pub fn hit_chunk(&self, chunk: &RayChunk, rays: &[Ray], lanes: u32, ray_t_min: Float, closest: &[Float], hit_records: &mut [HitRecord]) -> u32 {
+        stats::record(|s| s.sphere_tests += lanes.count_ones() as u64);
+        let time = Float4::from_array(chunk.time.map(|time| time.clamp(0.0, 1.0)));
+        let oc: [Float4; 3] = std::array::from_fn(|axis| {
+            Float4::splat(self.center[axis]) + Float4::splat(self.motion[axis]) * time - chunk.origin[axis]
+        });
+        let [dx, dy, dz] = chunk.direction;
+        let a = dx * dx + dy * dy + dz * dz;
+        let h = dx * oc[0] + dy * oc[1] + dz * oc[2];
+        let c = oc[0] * oc[0] + oc[1] * oc[1] + oc[2] * oc[2] - Float4::splat(self.radius * self.radius);
+        let discriminant = h * h - a * c;
+        let sqrt_d = discriminant.sqrt();
+        let (a, h, discriminant, sqrt_d) = (a.to_array(), h.to_array(), discriminant.to_array(), sqrt_d.to_array());
+
+        let mut hits = 0;
+        for lane in (0..rays.len()).filter(|lane| lanes & (1 << lane) != 0) {
+            if discriminant[lane] < 0.0 {
+                continue;
+            }
+            if let Some(root) = nearest_root(h[lane], sqrt_d[lane], a[lane], ray_t_min, closest[lane]) {
+                self.record(&rays[lane], root, &mut hit_records[lane]);
+                hits |= 1 << lane;
+            }
+        }
+        hits
+    }
+}
+
+// The nearer root of the ray and sphere equation within the interval, or the
+// farther one from inside the sphere.
+fn nearest_root(h: Float, sqrt_d: Float, a: Float, ray_t_min: Float, ray_t_max: Float) -> Option<Float> {
+    let mut root = (h - sqrt_d) / a;
+    if root <= ray_t_min || ray_t_max <= root {
+        root = (h + sqrt_d) / a;
+        if root <= ray_t_min || ray_t_max <= root {
+            return None;
+        }
+    }
+    Some(root)
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, ray_t_min: f64, ray_t_max: f64, hit_record: &mut HitRecord) -> bool {
-        let oc = self.center - ray.origin;
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool {
+        stats::record(|s| s.sphere_tests += 1);
+        let center = self.center_at(ray.time);
+        let oc = center - ray.origin;
         let a = ray.direction.length_squared();
         let h = ray.direction.dot(&oc);
         //let b = -2.0 * ray.direction.dot(oc);
@@ -60,44 +191,196 @@ impl Hittable for Sphere {
         }
         let sqrt_d = discriminant.sqrt();
 
-        let mut root = (h - sqrt_d) / a;
-        if root <= ray_t_min || ray_t_max <= root {
-            root = (h + sqrt_d) / a;
-            if root <= ray_t_min || ray_t_max <= root {
-                return false;
+        match nearest_root(h, sqrt_d, a, ray_t_min, ray_t_max) {
+            Some(root) => {
+                self.record(ray, root, hit_record);
+                true
             }
+            None => false,
         }
+    }
 
-        hit_record.t = root;
-        hit_record.point = ray.at(hit_record.t);
-        hit_record.material = self.material;
-        let outward_normal = (hit_record.point - self.center) / self.radius;
-        hit_record.set_face_normal(ray, outward_normal);
+    fn sphere(&self) -> Option<&Sphere> {
+        Some(self)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let start = Aabb::new(self.center - r, self.center + r);
+        let end = self.center + self.motion;
+        start.union(&Aabb::new(end - r, end + r))
+    }
+}
+
+// Longitude and latitude of a point on the unit sphere, both in [0, 1].
+fn sphere_uv(p: Normal3) -> (Float, Float) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+// Glowing sphere that integrators can aim light paths and shadow rays at.
+#[derive(Clone, Copy, Debug)]
+pub struct SphereLight {
+    pub center: Point3,
+    pub radius: Float,
+    pub emission: Color,
+}
+
+impl SphereLight {
+    pub fn area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
 
-        return true
+    // Uniformly distributed point on the surface and the outward normal there.
+    pub fn sample_point(&self, u: (Float, Float)) -> (Point3, Normal3) {
+        let normal = Normal3::from_vec(sample_unit_vector(u));
+        (self.center + self.radius * normal, normal)
     }
 }
 
 pub struct HittableList {
     pub vec: Vec<Box<dyn Hittable>>,
+    pub lights: Vec<SphereLight>,
+    // built by `build_bvh` once the scene is complete, until then every object is tested
+    bvh: Option<Bvh>,
+}
+
+impl HittableList {
+    pub fn new() -> HittableList {
+        HittableList { vec: vec![], lights: vec![], bvh: None }
+    }
+
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<Aabb> = self.vec.iter().map(|object| object.bounding_box()).collect();
+        self.bvh = Some(Bvh::build(&bounds));
+    }
+
+    pub fn push_named(&mut self, name: &str, object: Box<dyn Hittable>) {
+        self.vec.push(Box::new(Named::new(name, object)));
+    }
+
+    // Adds a sphere with a diffuse light material and registers it as a light.
+    pub fn push_light(&mut self, name: &str, light: SphereLight) {
+        let material = Material::DiffuseLight { emission: light.emission };
+        self.push_named(name, Box::new(Sphere::new(light.center, light.radius, Some(material))));
+        self.lights.push(light);
+    }
+
+    pub fn object_names(&self) -> Vec<String> {
+        self.vec.iter().filter_map(|object| object.name()).map(str::to_string).collect()
+    }
+
+    // First hits of the rays of a packet, None for those that hit nothing. Every ray
+    // gets exactly the hit `hit` would find for it. Without a BVH there is nothing to
+    // share between the rays and this returns None.
+    pub fn hit_packet(&self, packet: &RayPacket, ray_t_min: Float, ray_t_max: Float) -> Option<Vec<Option<HitRecord>>> {
+        let bvh = self.bvh.as_ref()?;
+        let count = packet.rays.len();
+        let mut closest = vec![ray_t_max; packet.lanes()];
+        // per ray like the record `hit` reuses between objects
+        let mut temp_hit_records = vec![HitRecord::empty(); count];
+        let mut hits = vec![None; count];
+
+        bvh.traverse_packet(packet, ray_t_min, &mut closest, |index, lanes, closest| {
+            let object = &self.vec[index];
+            let mut hit_lanes = 0;
+            match object.sphere() {
+                Some(sphere) => {
+                    for (c, chunk) in packet.chunks().iter().enumerate() {
+                        let chunk_lanes = (lanes >> (4 * c)) & 0xf;
+                        if chunk_lanes == 0 {
+                            continue;
+                        }
+                        let range = 4 * c..(4 * c + 4).min(count);
+                        let chunk_hits = sphere.hit_chunk(chunk, &packet.rays[range.clone()], chunk_lanes, ray_t_min, &closest[range.clone()], &mut temp_hit_records[range]);
+                        hit_lanes |= chunk_hits << (4 * c);
+                    }
+                    let id = object.name().map_or(0, id_from_name);
+                    for lane in (0..count).filter(|lane| hit_lanes & (1 << lane) != 0) {
+                        temp_hit_records[lane].object_id = id;
+                    }
+                }
+                None => {
+                    for lane in (0..count).filter(|lane| lanes & (1 << lane) != 0) {
+                        temp_hit_records[lane].object_id = 0;
+                        if object.hit(&packet.rays[lane], ray_t_min, closest[lane], &mut temp_hit_records[lane]) {
+                            hit_lanes |= 1 << lane;
+                        }
+                    }
+                }
+            }
+            for lane in (0..count).filter(|lane| hit_lanes & (1 << lane) != 0) {
+                let record = &mut temp_hit_records[lane];
+                if record.object_id == 0 {
+                    record.object_id = index as u32 + 1;
+                }
+                closest[lane] = record.t;
+                hits[lane] = Some(*record);
+            }
+        });
+        Some(hits)
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, ray_t_min: f64, ray_t_max: f64, hit_record: &mut HitRecord) -> bool {
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool {
         let mut temp_hit_record: HitRecord = HitRecord::empty();
         let mut hit_anything = false;
         let mut closest_so_far = ray_t_max;
 
-        for object in &self.vec {
-            if object.hit(ray, ray_t_min, ray_t_max, &mut temp_hit_record) {
-                hit_anything = true;
-                if temp_hit_record.t < closest_so_far {
-                    closest_so_far = temp_hit_record.t;
-                    *hit_record = temp_hit_record;
+        let mut hit_object = |index: usize, closest: Float| {
+            temp_hit_record.object_id = 0;
+            if !self.vec[index].hit(ray, ray_t_min, closest, &mut temp_hit_record) {
+                return None;
+            }
+            if temp_hit_record.object_id == 0 {
+                temp_hit_record.object_id = index as u32 + 1;
+            }
+            *hit_record = temp_hit_record;
+            Some(temp_hit_record.t)
+        };
+
+        match &self.bvh {
+            Some(bvh) => bvh.traverse(ray, ray_t_min, ray_t_max, |index, closest| {
+                let t = hit_object(index, closest);
+                hit_anything |= t.is_some();
+                t
+            }),
+            None => {
+                for index in 0..self.vec.len() {
+                    if let Some(t) = hit_object(index, closest_so_far) {
+                        hit_anything = true;
+                        closest_so_far = t;
+                    }
                 }
             }
         }
 
         hit_anything
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Aabb {
+        let mut boxes = self.vec.iter().map(|object| object.bounding_box());
+        let first = boxes.next().unwrap_or(Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)));
+        boxes.fold(first, |a, b| a.union(&b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_sphere_bounds_contain_it_throughout_the_shutter() {
+        let sphere = Sphere::moving(Point3::new(-1.0, 0.0, 2.0), Point3::new(3.0, -2.0, 0.5), 0.75, None);
+        let bounds = sphere.bounding_box();
+        let r = Vec3::new(0.75, 0.75, 0.75);
+        for step in 0..=20 {
+            let center = sphere.center_at(step as Float / 20.0);
+            let (low, high) = (center - r, center + r);
+            assert!(low.x >= bounds.min.x && low.y >= bounds.min.y && low.z >= bounds.min.z, "{:?} below {:?}", low, bounds.min);
+            assert!(high.x <= bounds.max.x && high.y <= bounds.max.y && high.z <= bounds.max.z, "{:?} above {:?}", high, bounds.max);
+        }
+    }
+}
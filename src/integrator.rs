@@ -0,0 +1,538 @@
+use crate::bdpt::{BdptIntegrator, PinholeCamera};
+use crate::color::Color;
+use crate::film::{Features, SplatFilm};
+use crate::float::Float;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::libs::sample_unit_vector;
+use crate::material::Material;
+use crate::metropolis::MetropolisSettings;
+use crate::photon::PhotonMappingIntegrator;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::stats;
+use crate::vec3::{Point3, Vec3};
+
+// Turns a camera ray into the radiance arriving along it, filling `features` with
+// what the first hit looked like.
+pub trait Integrator {
+    fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color;
+
+    // Contributions to other pixels than the one being sampled, the camera moves
+    // them into the film after every pass.
+    fn splats(&self) -> Option<&SplatFilm> {
+        None
+    }
+
+    // Called before every pass with the pass number and the time the shutter opens.
+    fn start_pass(&mut self, _world: &HittableList, _pass: u32, _time: Float) {}
+
+    // Whether the camera may find first hits in packets and hand them to `li_from_hit`.
+    fn takes_first_hit(&self) -> bool {
+        false
+    }
+
+    // `li` for a camera ray whose first hit, or None for a miss, was found already.
+    fn li_from_hit(&self, ray: &Ray, _hit: Option<HitRecord>, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        self.li(ray, world, sampler, features)
+    }
+}
+
+// The first hit handed in if there is one, otherwise the closest hit of the ray.
+fn trace(ray: &Ray, world: &HittableList, first_hit: &mut Option<Option<HitRecord>>, hit_record: &mut HitRecord) -> bool {
+    match first_hit.take() {
+        Some(Some(hit)) => {
+            *hit_record = hit;
+            true
+        }
+        Some(None) => false,
+        None => world.hit(ray, 0.001, Float::INFINITY, hit_record),
+    }
+}
+
+// Rendering modes, the path tracer and debug views for scene layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IntegratorKind {
+    #[default]
+    Path,
+    // bidirectional, for light that is hard to reach from the camera
+    Bdpt,
+    // progressive photon mapping, `photons` per pass gathered within `radius` at
+    // first, for caustics
+    PhotonMapping { photons: u32, radius: Float },
+    // Metropolis light transport over paths of the path tracer, or the bidirectional
+    // one, driven by the camera rather than per pixel
+    Metropolis(MetropolisSettings),
+    Normals,
+    // distance along the ray, white up close fading to black at `far`
+    Depth { far: Float },
+    Uv,
+    Albedo,
+    // grid lines along the sphere UV parameterization. There are no triangle
+    // meshes, so there is no barycentric wireframe to draw
+    UvGrid,
+    // share of the hemisphere not blocked within `radius`
+    AmbientOcclusion { radius: Float },
+}
+
+// Cycled through by the viewer.
+const MODES: [IntegratorKind; 7] = [
+    IntegratorKind::Path,
+    IntegratorKind::Normals,
+    IntegratorKind::Depth { far: 10.0 },
+    IntegratorKind::Uv,
+    IntegratorKind::Albedo,
+    IntegratorKind::UvGrid,
+    IntegratorKind::AmbientOcclusion { radius: 1.0 },
+];
+
+impl IntegratorKind {
+    // Accepts path, bdpt, ppm, mlt, mlt-bdpt, normals, depth[:far], uv, albedo, uv-grid and ao[:radius].
+    pub fn parse(name: &str) -> Option<IntegratorKind> {
+        let (kind, parameter) = match name.split_once(':') {
+            Some((kind, parameter)) => (kind, Some(parameter.parse::<Float>().ok().filter(|p| *p > 0.0 && p.is_finite())?)),
+            None => (name, None),
+        };
+        // only the depth range and the occlusion radius are given after a colon
+        if parameter.is_some() && kind != "depth" && kind != "ao" {
+            return None;
+        }
+        match kind {
+            "path" => Some(IntegratorKind::Path),
+            "bdpt" => Some(IntegratorKind::Bdpt),
+            "ppm" => Some(IntegratorKind::PhotonMapping { photons: 100_000, radius: 0.05 }),
+            "mlt" => Some(IntegratorKind::Metropolis(MetropolisSettings::new(false))),
+            "mlt-bdpt" => Some(IntegratorKind::Metropolis(MetropolisSettings::new(true))),
+            "normals" => Some(IntegratorKind::Normals),
+            "depth" => Some(IntegratorKind::Depth { far: parameter.unwrap_or(10.0) }),
+            "uv" => Some(IntegratorKind::Uv),
+            "albedo" => Some(IntegratorKind::Albedo),
+            "uv-grid" => Some(IntegratorKind::UvGrid),
+            "ao" => Some(IntegratorKind::AmbientOcclusion { radius: parameter.unwrap_or(1.0) }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegratorKind::Path => "path",
+            IntegratorKind::Bdpt => "bdpt",
+            IntegratorKind::PhotonMapping { .. } => "ppm",
+            IntegratorKind::Metropolis(settings) if settings.bidirectional => "mlt-bdpt",
+            IntegratorKind::Metropolis(_) => "mlt",
+            IntegratorKind::Normals => "normals",
+            IntegratorKind::Depth { .. } => "depth",
+            IntegratorKind::Uv => "uv",
+            IntegratorKind::Albedo => "albedo",
+            IntegratorKind::UvGrid => "uv-grid",
+            IntegratorKind::AmbientOcclusion { .. } => "ao",
+        }
+    }
+
+    // The mode after this one, back to the path tracer after the last.
+    pub fn next(&self) -> IntegratorKind {
+        let index = MODES.iter().position(|mode| mode.name() == self.name()).unwrap_or(0);
+        MODES[(index + 1) % MODES.len()]
+    }
+
+    // `camera` is the pinhole light paths connect to, None when the camera has a lens
+    // or another projection. Metropolis gets the integrator its chains evaluate paths with.
+    // `spectral` makes the path tracer, on its own or under Metropolis, trace wavelengths.
+    pub fn build(&self, max_bounces: u32, russian_roulette_depth: u32, seed: u64, camera: Option<PinholeCamera>, spectral: bool) -> Box<dyn Integrator> {
+        match *self {
+            IntegratorKind::Path if spectral => Box::new(SpectralPathIntegrator { max_bounces, russian_roulette_depth }),
+            IntegratorKind::Path => Box::new(SimplePathIntegrator { max_bounces, russian_roulette_depth }),
+            IntegratorKind::Bdpt => Box::new(BdptIntegrator::new(max_bounces, camera)),
+            IntegratorKind::PhotonMapping { photons, radius } => {
+                Box::new(PhotonMappingIntegrator::new(max_bounces, russian_roulette_depth, photons, radius, seed))
+            }
+            IntegratorKind::Metropolis(settings) if settings.bidirectional => Box::new(BdptIntegrator::new(max_bounces, None)),
+            IntegratorKind::Metropolis(_) if spectral => Box::new(SpectralPathIntegrator { max_bounces, russian_roulette_depth }),
+            IntegratorKind::Metropolis(_) => Box::new(SimplePathIntegrator { max_bounces, russian_roulette_depth }),
+            IntegratorKind::Normals => Box::new(NormalsIntegrator),
+            IntegratorKind::Depth { far } => Box::new(DepthIntegrator { far }),
+            IntegratorKind::Uv => Box::new(UvIntegrator),
+            IntegratorKind::Albedo => Box::new(AlbedoIntegrator),
+            IntegratorKind::UvGrid => Box::new(UvGridIntegrator),
+            IntegratorKind::AmbientOcclusion { radius } => Box::new(AmbientOcclusionIntegrator { radius }),
+        }
+    }
+}
+
+pub fn sky_color(ray: &Ray) -> Color {
+    let unit = ray.direction.normalize();
+    let a = 0.5 * (unit.y + 1.0);
+    (1.0 - a) * Color::WHITE + a * Color::new(0.5, 0.7, 1.0)
+}
+
+// Unidirectional path tracer lit by the sky and emissive materials. Once past
+// `russian_roulette_depth` bounces a path survives with a probability based on its
+// throughput. Paths absorbed by a surface see the sky, as they always have, only
+// lights end them with their own emission.
+pub struct SimplePathIntegrator {
+    pub max_bounces: u32,
+    pub russian_roulette_depth: u32,
+}
+
+impl Integrator for SimplePathIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        self.trace_path(ray, None, world, sampler, features)
+    }
+
+    fn takes_first_hit(&self) -> bool {
+        true
+    }
+
+    fn li_from_hit(&self, ray: &Ray, hit: Option<HitRecord>, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        self.trace_path(ray, Some(hit), world, sampler, features)
+    }
+}
+
+impl SimplePathIntegrator {
+    // The first hit is recorded in `features` for the denoiser, along with how its
+    // light splits into the AOV passes. `first_hit` is the hit of the camera ray if
+    // it is known already.
+    fn trace_path(&self, ray: &Ray, mut first_hit: Option<Option<HitRecord>>, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        let mut radiance = Color::BLACK;
+        // attenuation over survival probability of all bounces so far
+        let mut throughput = Color::WHITE;
+        let mut ray = *ray;
+        // emission, weight and specularity of the first hit, the light it reflects
+        // is only known once the path ends
+        let mut first_scatter = None;
+        let mut depth = 0;
+
+        loop {
+            let mut hit_record = HitRecord::empty();
+            let traced = depth < self.max_bounces;
+            let hit = traced && trace(&ray, world, &mut first_hit, &mut hit_record);
+            if hit {
+                if depth == 0 {
+                    *features = Features::first_hit(&ray, &hit_record);
+                } else if depth == 1 {
+                    features.first_bounce_light = hit_record.material.map_or(Color::BLACK, |m| m.emitted());
+                }
+                features.rays += 1;
+
+                let mut scattered = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, 0.0), ray.time);
+                let mut attenuation = Color::BLACK;
+                if let Some(material) = hit_record.material {
+                    if material.scatter(&ray, &hit_record, &mut attenuation, &mut scattered, sampler) {
+                        radiance = radiance + throughput * material.emitted();
+                        let mut survival = 1.0;
+                        if depth + 1 >= self.russian_roulette_depth {
+                            survival = (throughput * attenuation).max_component().min(0.95);
+                            if sampler.get_1d() >= survival {
+                                stats::record(|s| s.russian_roulette_kills += 1);
+                                break;
+                            }
+                        }
+                        if depth == 0 {
+                            first_scatter = Some((material.emitted(), attenuation / survival, material.is_specular()));
+                        }
+                        throughput = throughput * attenuation / survival;
+                        ray = scattered;
+                        depth += 1;
+                        continue;
+                    }
+                    if let Material::DiffuseLight { emission } = material {
+                        radiance = radiance + throughput * emission;
+                        break;
+                    }
+                }
+            }
+
+            let sky = sky_color(&ray);
+            if depth == 0 {
+                // the sky is its own albedo so that demodulating it leaves nothing to denoise
+                *features = Features { albedo: sky, emission: sky, rays: features.rays, ..Features::default() };
+            } else if depth == 1 {
+                features.first_bounce_light = sky;
+            }
+            if !traced {
+                stats::record(|s| s.max_bounce_kills += 1);
+            } else if !hit {
+                features.rays += 1;
+            }
+            radiance = radiance + throughput * sky;
+            break;
+        }
+
+        if let Some((emitted, weight, specular)) = first_scatter {
+            let reflected = radiance - emitted;
+            if specular {
+                features.specular = reflected;
+            } else {
+                features.diffuse_direct = features.first_bounce_light * weight;
+                features.diffuse_indirect = reflected - features.diffuse_direct;
+            }
+        }
+        radiance
+    }
+}
+
+// The path tracer with wavelengths in place of RGB. Every path carries a hero
+// wavelength and three more spread evenly from it, albedos and emission are uplifted
+// to their spectra and dispersive glass bends each wavelength its own way, at which
+// point the path follows the hero alone. Radiance goes back to RGB through XYZ, so
+// the film is the same as for the RGB path tracer. The AOV passes only get the first
+// hit, the light is not split up.
+pub struct SpectralPathIntegrator {
+    pub max_bounces: u32,
+    pub russian_roulette_depth: u32,
+}
+
+impl Integrator for SpectralPathIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        self.trace_path(ray, None, world, sampler, features)
+    }
+
+    fn takes_first_hit(&self) -> bool {
+        true
+    }
+
+    fn li_from_hit(&self, ray: &Ray, hit: Option<HitRecord>, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        self.trace_path(ray, Some(hit), world, sampler, features)
+    }
+}
+
+impl SpectralPathIntegrator {
+    fn trace_path(&self, ray: &Ray, mut first_hit: Option<Option<HitRecord>>, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        let mut wavelengths = SampledWavelengths::sample_visible(sampler.get_1d());
+        let mut radiance = SampledSpectrum::splat(0.0);
+        let mut throughput = SampledSpectrum::splat(1.0);
+        let mut ray = *ray;
+        let mut depth = 0;
+
+        loop {
+            let mut hit_record = HitRecord::empty();
+            let traced = depth < self.max_bounces;
+            let hit = traced && trace(&ray, world, &mut first_hit, &mut hit_record);
+            if hit {
+                if depth == 0 {
+                    *features = Features::first_hit(&ray, &hit_record);
+                }
+                features.rays += 1;
+
+                if let Some(material) = hit_record.material {
+                    radiance = radiance + throughput * SampledSpectrum::from_rgb(material.emitted(), &wavelengths);
+                    if material.is_dispersive() {
+                        wavelengths.terminate_secondary();
+                    }
+                    let mut scattered = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, 0.0), ray.time);
+                    let mut attenuation = Color::BLACK;
+                    if material.at_wavelength(wavelengths.hero()).scatter(&ray, &hit_record, &mut attenuation, &mut scattered, sampler) {
+                        let attenuation = SampledSpectrum::from_rgb(attenuation, &wavelengths);
+                        let mut survival = 1.0;
+                        if depth + 1 >= self.russian_roulette_depth {
+                            survival = (throughput * attenuation).max_value().min(0.95);
+                            if sampler.get_1d() >= survival {
+                                stats::record(|s| s.russian_roulette_kills += 1);
+                                break;
+                            }
+                        }
+                        throughput = throughput * attenuation / survival;
+                        ray = scattered;
+                        depth += 1;
+                        continue;
+                    }
+                    // as in the RGB path tracer, only lights end the path, their emission is already in
+                    if matches!(material, Material::DiffuseLight { .. }) {
+                        break;
+                    }
+                }
+            }
+
+            let sky = sky_color(&ray);
+            if depth == 0 {
+                *features = Features { albedo: sky, emission: sky, rays: features.rays, ..Features::default() };
+            }
+            if !traced {
+                stats::record(|s| s.max_bounce_kills += 1);
+            } else if !hit {
+                features.rays += 1;
+            }
+            radiance = radiance + throughput * SampledSpectrum::from_rgb(sky, &wavelengths);
+            break;
+        }
+        wavelengths.to_rgb(radiance)
+    }
+}
+
+// Finds the first hit and records it in `features`, on a miss the features are
+// left empty.
+fn first_hit(ray: &Ray, world: &HittableList, features: &mut Features) -> Option<HitRecord> {
+    let mut hit_record = HitRecord::empty();
+    let hit = world.hit(ray, 0.001, Float::INFINITY, &mut hit_record);
+    if hit {
+        *features = Features::first_hit(ray, &hit_record);
+    }
+    features.rays = 1;
+    hit.then_some(hit_record)
+}
+
+pub struct NormalsIntegrator;
+
+impl Integrator for NormalsIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, _sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        match first_hit(ray, world, features) {
+            Some(hit) => Color::new(0.5 * (hit.normal.x + 1.0), 0.5 * (hit.normal.y + 1.0), 0.5 * (hit.normal.z + 1.0)),
+            None => Color::BLACK,
+        }
+    }
+}
+
+pub struct DepthIntegrator {
+    pub far: Float,
+}
+
+impl Integrator for DepthIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, _sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        match first_hit(ray, world, features) {
+            Some(_) => Color::gray((1.0 - features.depth / self.far).max(0.0)),
+            None => Color::BLACK,
+        }
+    }
+}
+
+// Checkerboard tinted by the surface coordinates, red along u and green along v.
+pub struct UvIntegrator;
+
+impl Integrator for UvIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, _sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        match first_hit(ray, world, features) {
+            Some(hit) => {
+                let checker = ((hit.u * 16.0).floor() + (hit.v * 8.0).floor()) as i64 % 2 == 0;
+                Color::new(hit.u, hit.v, 0.5) * if checker { 1.0 } else { 0.5 }
+            }
+            None => Color::BLACK,
+        }
+    }
+}
+
+pub struct AlbedoIntegrator;
+
+impl Integrator for AlbedoIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, _sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        first_hit(ray, world, features);
+        features.albedo
+    }
+}
+
+pub struct UvGridIntegrator;
+
+impl Integrator for UvGridIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, _sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        let Some(hit) = first_hit(ray, world, features) else {
+            return Color::BLACK;
+        };
+        let near_line = |x: Float| !(0.03..=0.97).contains(&x.fract());
+        // the lines at the poles shrink to a point and would cover the pole
+        let latitude = hit.v * 12.0;
+        if near_line(hit.u * 24.0) || (near_line(latitude) && (0.5..11.5).contains(&latitude)) {
+            return Color::WHITE;
+        }
+        // facing ratio shading so the shapes stay readable between the lines
+        let facing = hit.normal.dot(&ray.direction.normalize()).abs();
+        Color::gray(0.1 + 0.3 * facing)
+    }
+}
+
+pub struct AmbientOcclusionIntegrator {
+    pub radius: Float,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        let Some(hit) = first_hit(ray, world, features) else {
+            return Color::WHITE;
+        };
+        // cosine weighted directions, so the fraction of unblocked rays is the
+        // occlusion as a diffuse surface sees it
+        let mut direction = hit.normal + sample_unit_vector(sampler.get_2d());
+        if direction.length_squared() < 1e-12 {
+            direction = hit.normal.to_vec();
+        }
+        let occlusion_ray = Ray::new(hit.point, direction.normalize(), ray.time);
+        stats::record(|s| s.shadow_rays += 1);
+        let mut occluder = HitRecord::empty();
+        if world.hit(&occlusion_ray, 0.001, self.radius, &mut occluder) {
+            Color::BLACK
+        } else {
+            Color::WHITE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::sampler::SamplerKind;
+
+    fn radiance(world: &HittableList, ray: &Ray, samples: u32) -> Vec<Color> {
+        let integrator = SimplePathIntegrator { max_bounces: 4, russian_roulette_depth: 3 };
+        let mut sampler = SamplerKind::Independent.build(samples, 1);
+        (0..samples).map(|sample| {
+            sampler.start_pixel_sample(0, 0, sample);
+            integrator.li(ray, world, sampler.as_mut(), &mut Features::default())
+        }).collect()
+    }
+
+    #[test]
+    fn only_depth_and_ao_take_a_parameter() {
+        assert_eq!(IntegratorKind::parse("depth:4"), Some(IntegratorKind::Depth { far: 4.0 }));
+        assert_eq!(IntegratorKind::parse("depth"), Some(IntegratorKind::Depth { far: 10.0 }));
+        assert_eq!(IntegratorKind::parse("ao:0.5"), Some(IntegratorKind::AmbientOcclusion { radius: 0.5 }));
+        assert_eq!(IntegratorKind::parse("uv-grid"), Some(IntegratorKind::UvGrid));
+        for name in ["path:2", "bdpt:1", "normals:3", "depth:0", "depth:inf", "depth:NaN", "ao:-1", "ao:", "sky"] {
+            assert_eq!(IntegratorKind::parse(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn normals_and_depth_of_a_sphere() {
+        let mut world = HittableList::new();
+        world.push_named("ball", Box::new(Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, None)));
+        let mut sampler = SamplerKind::Independent.build(1, 1);
+        let mut features = Features::default();
+        let towards = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let away = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, 1.0), 0.0);
+        // the nearest point faces the camera, 1.5 away
+        assert_eq!(NormalsIntegrator.li(&towards, &world, sampler.as_mut(), &mut features), Color::new(0.5, 0.5, 1.0));
+        let depth = DepthIntegrator { far: 3.0 };
+        assert_eq!(depth.li(&towards, &world, sampler.as_mut(), &mut features), Color::gray(0.5));
+        assert_eq!(DepthIntegrator { far: 1.0 }.li(&towards, &world, sampler.as_mut(), &mut features), Color::BLACK);
+        assert_eq!(NormalsIntegrator.li(&away, &world, sampler.as_mut(), &mut features), Color::BLACK);
+        assert_eq!(depth.li(&away, &world, sampler.as_mut(), &mut features), Color::BLACK);
+    }
+
+    #[test]
+    fn absorbed_paths_see_the_sky_and_lights_their_emission() {
+        let ray = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, -1.0), 0.0);
+        // fuzz this large sends most reflections below the surface, where the metal absorbs them
+        let mut metal = HittableList::new();
+        let material = Material::Metal { albedo: Color::WHITE, fuzziness: 10.0 };
+        metal.push_named("metal", Box::new(Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, Some(material))));
+        assert!(radiance(&metal, &ray, 64).iter().all(|color| color.max_component() > 0.0));
+
+        let mut light = HittableList::new();
+        let emission = Color::new(4.0, 2.0, 1.0);
+        light.push_named("light", Box::new(Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, Some(Material::DiffuseLight { emission }))));
+        assert!(radiance(&light, &ray, 4).iter().all(|color| *color == emission));
+    }
+
+    #[test]
+    fn spectral_absorbed_paths_see_the_sky() {
+        let integrator = SpectralPathIntegrator { max_bounces: 4, russian_roulette_depth: 3 };
+        let ray = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut metal = HittableList::new();
+        let material = Material::Metal { albedo: Color::WHITE, fuzziness: 10.0 };
+        metal.push_named("metal", Box::new(Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, Some(material))));
+        let mut sampler = SamplerKind::Independent.build(64, 1);
+        for sample in 0..64 {
+            sampler.start_pixel_sample(0, 0, sample);
+            assert!(integrator.li(&ray, &metal, sampler.as_mut(), &mut Features::default()).max_component() > 0.0);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::float::Float;
+use crate::vec3::Point3;
+
+// Balanced kd-tree over points, stored as a sorted array: the node of a range is
+// its middle element and splits the rest along `axes[middle]`.
+pub struct KdTree<T> {
+    items: Vec<(Point3, T)>,
+    axes: Vec<u8>,
+}
+
+impl<T> KdTree<T> {
+    pub fn new(mut items: Vec<(Point3, T)>) -> KdTree<T> {
+        let mut axes = vec![0; items.len()];
+        build(&mut items, &mut axes);
+        KdTree { items, axes }
+    }
+
+    // Calls `f` with every item within `radius` of `center` and its squared distance.
+    pub fn for_each_within(&self, center: Point3, radius: Float, mut f: impl FnMut(&T, Float)) {
+        self.search(0, self.items.len(), center, radius * radius, &mut f);
+    }
+
+    fn search(&self, start: usize, end: usize, center: Point3, radius_squared: Float, f: &mut impl FnMut(&T, Float)) {
+        if start >= end {
+            return;
+        }
+        let middle = (start + end) / 2;
+        let (point, item) = &self.items[middle];
+        let distance_squared = (*point - center).length_squared();
+        if distance_squared <= radius_squared {
+            f(item, distance_squared);
+        }
+        let axis = self.axes[middle] as usize;
+        let offset = center[axis] - point[axis];
+        // the side the centre is on first, the other only if the sphere reaches across
+        let (near, far) = if offset <= 0.0 { ((start, middle), (middle + 1, end)) } else { ((middle + 1, end), (start, middle)) };
+        self.search(near.0, near.1, center, radius_squared, f);
+        if offset * offset <= radius_squared {
+            self.search(far.0, far.1, center, radius_squared, f);
+        }
+    }
+}
+
+// Splits along the widest axis at the median, everything before the middle is no
+// further along the axis than it and everything after no nearer.
+fn build<T>(items: &mut [(Point3, T)], axes: &mut [u8]) {
+    if items.len() <= 1 {
+        return;
+    }
+    let mut low = items[0].0;
+    let mut high = items[0].0;
+    for (point, _) in items.iter() {
+        low = Point3::new(low.x.min(point.x), low.y.min(point.y), low.z.min(point.z));
+        high = Point3::new(high.x.max(point.x), high.y.max(point.y), high.z.max(point.z));
+    }
+    let extent = high - low;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+
+    let middle = items.len() / 2;
+    items.select_nth_unstable_by(middle, |a, b| a.0[axis].total_cmp(&b.0[axis]));
+    axes[middle] = axis as u8;
+    let (left_items, right_items) = items.split_at_mut(middle);
+    let (left_axes, right_axes) = axes.split_at_mut(middle);
+    build(left_items, left_axes);
+    build(&mut right_items[1..], &mut right_axes[1..]);
+}
@@ -1,42 +1,51 @@
-use std::f64::consts::PI;
-use rand::Rng;
+use crate::float::Float;
+use crate::float::consts::PI;
+use std::fs;
+use std::io;
+use crate::color::Color;
 use crate::vec3::Vec3;
 
-pub fn write_color(color: &Vec3) -> String {
-    let ir = (255.999 * color.x).floor() as u8;
-    let ig = (255.999 * color.y).floor() as u8;
-    let ib = (255.999 * color.z).floor() as u8;
-
+pub fn write_color(color: &Color) -> String {
+    let [ir, ig, ib] = color.to_bytes();
     format!("{} {} {}\n", ir, ig, ib)
 }
 
-pub fn degrees_to_radians(degrees: f64) -> f64 {
-    degrees * PI / 180.0
-}
-
-pub fn random_vector() -> Vec3 {
-    random_vector_range(0.0,1.0)
-}
-
-pub fn random_unit_vector() -> Vec3 {
-    random_in_unit_sphere().normalize()
+// Writes a plain text PPM. Rows are stored bottom to top like the viewer expects.
+pub fn write_ppm(path: &str, pixels: &[Vec<Color>]) -> io::Result<()> {
+    let height = pixels.len();
+    let width = pixels.first().map_or(0, |row| row.len());
+    let mut contents = format!("P3\n{} {}\n255\n", width, height);
+    for row in pixels.iter().rev() {
+        for color in row {
+            contents.push_str(write_color(color).as_str());
+        }
+    }
+    fs::write(path, contents)
 }
 
-pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
-    let unit_sphere = random_unit_vector();
-    if unit_sphere.dot(normal) > 0.0 { unit_sphere } else { -unit_sphere }
+pub fn degrees_to_radians(degrees: Float) -> Float {
+    degrees * PI / 180.0
 }
 
-pub fn random_vector_range(min: f64, max: f64) -> Vec3 {
-    let mut rand = rand::thread_rng();
-    Vec3::new(rand.gen_range(min..max), rand.gen_range(min..max), rand.gen_range(min..max))
+// Uniformly distributed direction on the unit sphere from a 2D sample.
+pub fn sample_unit_vector(u: (Float, Float)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = random_vector_range(-1.0, 1.0);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
+// Concentric mapping of a 2D sample onto the unit disk.
+pub fn sample_unit_disk(u: (Float, Float)) -> (Float, Float) {
+    let a = 2.0 * u.0 - 1.0;
+    let b = 2.0 * u.1 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
     }
-}
\ No newline at end of file
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
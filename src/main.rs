@@ -1,86 +1,276 @@
+// Constants are written to f64 precision and round when built with f32.
+#![cfg_attr(feature = "f32", allow(clippy::excessive_precision))]
+
+mod float;
 mod vec3;
+mod color;
 mod libs;
 mod ray;
 mod hittable;
 mod camera;
 mod material;
+mod aabb;
+mod volume;
+mod cli;
+mod sampler;
+mod filter;
+mod film;
+mod denoise;
+mod aov;
+mod cryptomatte;
+mod bvh;
+mod transform;
+mod animation;
+mod projection;
+mod checkpoint;
+mod progress;
+mod stats;
+mod heatmap;
+mod integrator;
+mod bdpt;
+mod kdtree;
+mod photon;
+mod metropolis;
+mod spectrum;
+mod packet;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd;
+#[cfg(test)]
+mod test_file;
 
 use lazy_static::lazy_static;
-use pixel_canvas::{Canvas, Color};
+use pixel_canvas::Canvas;
 use pixel_canvas::canvas::CanvasInfo;
 use pixel_canvas::input::{Event, MouseState, WindowEvent};
 use pixel_canvas::input::glutin::event::{ElementState, KeyboardInput, VirtualKeyCode};
+use crate::aabb::Aabb;
+use crate::animation::{AnimatedMaterial, CameraAnimation, Interpolation, Track};
 use crate::camera::Camera;
-use crate::hittable::{HittableList, Sphere};
+use crate::color::Color;
+use crate::checkpoint::Checkpoint;
+use std::io::IsTerminal;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+use crate::cli::{Options, USAGE};
+use crate::denoise::denoise;
+use crate::film::Film;
+use crate::filter::{Filter, FilterKind};
+use crate::float::Float;
+use crate::hittable::{HittableList, Sphere, SphereLight};
+use crate::integrator::IntegratorKind;
 use crate::material::Material;
+use crate::progress::{CancelToken, Progress};
+use crate::projection::Projection;
+use crate::sampler::SamplerKind;
+use crate::transform::{Keyframe, KeyframedTransform};
 use crate::vec3::{Point3, Vec3};
+use crate::volume::{DensityGrid, Volume};
 
 const IMAGE_WIDTH: u32 = 350 * 2;
 const IMAGE_HEIGHT: u32 = 250 * 2;
 
 static mut VEC3_DEFAULT: Vec3 = Vec3 {x: 0.0, y: 0.0, z: 0.0};
 static mut CAMERA: Camera = Camera {
-    position: Vec3 {x: 0.0, y: 0.0, z: 0.0},
+    position: Point3 {x: 0.0, y: 0.0, z: 0.0},
     forward: Vec3 {x: 0.0, y: 0.0, z: 0.0},
     world_up: Vec3 {x: 0.0, y: 0.0, z: 0.0},
     u: Vec3 {x: 0.0, y: 0.0, z: 0.0},
     v: Vec3 {x: 0.0, y: 0.0, z: 0.0},
 
     focal_length: 1.0,
-    pixel00_loc: Vec3 {x: 0.0, y: 0.0, z: 0.0},
+    pixel00_loc: Point3 {x: 0.0, y: 0.0, z: 0.0},
     camera_center: Point3 {x: 0.0, y: 0.0, z: 0.0},
     pixel_delta_u: Vec3 {x: 0.0, y: 0.0, z: 0.0},
     pixel_delta_v: Vec3 {x: 0.0, y: 0.0, z: 0.0},
-    pixel_sample_scale: 1.0,
+    integrator: IntegratorKind::Path,
     max_bounces: 1,
     samples_per_pixel: 1,
+    time_limit: None,
+    russian_roulette_depth: 3,
+    spectral: false,
+    packet_size: 1,
     vfov: 1.0,
+    defocus_angle: 0.0,
+    defocus_disk_u: Vec3 {x: 0.0, y: 0.0, z: 0.0},
+    defocus_disk_v: Vec3 {x: 0.0, y: 0.0, z: 0.0},
+    shutter_open: 0.0,
+    shutter_close: 0.0,
+    sampler: SamplerKind::Independent,
+    seed: 0,
+    filter: Filter { kind: FilterKind::Box, radius: 0.5 },
+    min_samples: 16,
+    adaptive_threshold: 0.0,
+    aovs: vec![],
+    cryptomatte: false,
+    heatmaps: vec![],
+    projection: Projection::Perspective,
+    stereo: None,
+    checkpoint: None,
+    checkpoint_interval: Duration::from_secs(60),
+    resume_from: vec![],
 };
 static mut NEED_UPDATE: bool = true;
+static mut DENOISE: bool = false;
 
 fn main() {
-    let mut world: HittableList = HittableList { vec: vec![] };
-    world.vec.push(
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}", message);
+            }
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut world: HittableList = HittableList::new();
+    world.push_named(
+        "ground",
         Box::new(Sphere::new(
-            Vec3::new(0.0, -100.5, -1.0),
+            Point3::new(0.0, -100.5, -1.0),
             100.0,
-            Some(Material::Lambertian { albedo: Vec3::new(1.0, 0.1, 0.1) })
+            Some(Material::Lambertian { albedo: Color::new(1.0, 0.1, 0.1) })
         ))
     );
-    world.vec.push(
-        Box::new(Sphere::new(
-            Vec3::new(0.0, 0.0, -1.5),
-            0.5,
-            Some(Material::Lambertian { albedo: Vec3::new(0.5, 1.0, 0.0) })
+    world.push_named(
+        "green_ball",
+        Box::new(AnimatedMaterial::new(
+            Box::new(Sphere::moving(
+                Point3::new(0.0, 0.0, -1.5),
+                Point3::new(0.0, 0.25, -1.5),
+                0.5,
+                Some(Material::Lambertian { albedo: Color::new(0.5, 1.0, 0.0) })
+            ))
+        ).with_albedo(
+            Track::new()
+                .key(0.0, Color::new(0.5, 1.0, 0.0), Interpolation::CatmullRom)
+                .key(2.0, Color::new(1.0, 0.8, 0.0), Interpolation::CatmullRom)
+                .key(4.0, Color::new(0.5, 1.0, 0.0), Interpolation::CatmullRom)
         ))
     );
-    world.vec.push(
-        Box::new(Sphere::new(
-            Vec3::new(-2.0, 0.0, -1.5),
-            1.5,
-            Some(Material::Metal { albedo: Vec3::new(1.0, 1.0, 1.0), fuzziness: 1.0})
+    world.push_named(
+        "metal_ball",
+        Box::new(AnimatedMaterial::new(
+            Box::new(KeyframedTransform::new(
+                Box::new(Sphere::new(
+                    Point3::new(0.0, 0.0, 0.0),
+                    1.5,
+                    Some(Material::Metal { albedo: Color::new(1.0, 1.0, 1.0), fuzziness: 1.0})
+                )),
+                vec![
+                    Keyframe::new(0.0, Vec3::new(-2.0, 0.0, -1.5), Vec3::new(0.0, 0.0, 0.0), 1.0),
+                    Keyframe::new(1.0, Vec3::new(-2.2, 0.0, -1.5), Vec3::new(0.0, 0.0, 0.0), 1.0),
+                ],
+            ))
+        ).with_parameter(
+            // polishes up over four seconds, easing in and out
+            Track::new()
+                .bezier_key(0.0, 1.0, 1.0, 1.0)
+                .bezier_key(4.0, 0.1, 0.1, 0.1)
         ))
     );
-    world.vec.push(
+    world.push_named(
+        "glass_bubble",
         Box::new(Sphere::new(
-            Vec3::new(1.0, 0.5, -1.5),
+            Point3::new(1.0, 0.5, -1.5),
             0.5,
-            Some(Material::Dialectric { albedo: Vec3::new(1.0, 1.0, 1.0), refraction_index: 1.0 / 1.33})
+            Some(Material::Dialectric { albedo: Color::new(1.0, 1.0, 1.0), refraction_index: 1.0 / 1.33, dispersion: options.dispersion })
         ))
     );
-    world.vec.push(
+    world.push_named(
+        "glass_ball",
         Box::new(Sphere::new(
-            Vec3::new(1.0, 0.5, -1.5),
+            Point3::new(1.0, 0.5, -1.5),
             0.75,
-            Some(Material::Dialectric { albedo: Vec3::new(1.0, 1.0, 1.0), refraction_index: 1.33})
+            Some(Material::Dialectric { albedo: Color::new(1.0, 1.0, 1.0), refraction_index: 1.33, dispersion: options.dispersion })
         ))
     );
 
-    use std::time::Instant;
+    if options.lamp {
+        // behind and above the glass ball, so its caustic falls on the ground in front
+        let lamp = SphereLight { center: Point3::new(1.3, 2.0, -2.6), radius: 0.2, emission: Color::new(40.0, 40.0, 40.0) };
+        world.push_light("lamp", lamp);
+    }
+
+    let volume_bounds = Aabb::new(Point3::new(-0.4, -0.5, -3.6), Point3::new(1.0, 1.5, -2.2));
+    if options.smoke {
+        world.push_named("smoke", Box::new(Volume::procedural_smoke(volume_bounds, 64, 7)));
+    }
+    if let Some(path) = &options.volume {
+        let volume = load_volume(path, &options, volume_bounds).unwrap_or_else(|e| {
+            eprintln!("could not load volume: {}", e);
+            std::process::exit(1);
+        });
+        world.push_named("volume", Box::new(volume));
+    }
+
+    world.build_bvh();
+
     let now = Instant::now();
 
-    //let mut camera = Camera::new(Vec3::new(0.0, 0.0, 0.0), 1.0, 4, 255, 90.0);
-    unsafe { CAMERA = Camera::new(Vec3::new(0.0, 0.0, 0.0), 1.0, 8, 1, 90.0); }
+    let mut camera = Camera::new(Point3::new(0.0, 0.0, 0.0), 1.0, options.max_bounces, options.samples_per_pixel, 90.0);
+    camera.time_limit = options.time_limit;
+    camera.integrator = options.integrator;
+    camera.spectral = options.spectral;
+    camera.packet_size = options.packet_size;
+    camera.sampler = options.sampler;
+    camera.seed = options.seed;
+    camera.defocus_angle = options.defocus_angle;
+    (camera.shutter_open, camera.shutter_close) = options.shutter;
+    camera.filter = options.filter;
+    camera.min_samples = options.min_samples;
+    camera.adaptive_threshold = options.adaptive_threshold;
+    camera.aovs = options.aovs.clone();
+    camera.cryptomatte = options.cryptomatte_ranks > 0;
+    camera.heatmaps = options.heatmaps.clone();
+    camera.projection = options.projection;
+    camera.stereo = options.stereo;
+    camera.checkpoint_interval = options.checkpoint_interval;
+
+    if let Some(path) = &options.output {
+        if options.stats {
+            stats::enable();
+        }
+        let cancel = CancelToken::new();
+        let mut progress = None;
+        let mut progress_bar = None;
+        if options.progress && std::io::stderr().is_terminal() {
+            let (sender, receiver) = mpsc::channel();
+            progress = Some(sender);
+            progress_bar = Some(std::thread::spawn(move || show_progress(receiver)));
+        }
+        if options.progress && std::io::stdin().is_terminal() {
+            eprintln!("Press Enter to stop early and keep the image rendered so far.");
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                if std::io::stdin().read_line(&mut String::new()).is_ok_and(|n| n > 0) {
+                    cancel.cancel();
+                }
+            });
+        }
+
+        render_output(&mut camera, path, &options, &world, progress.as_ref(), &cancel);
+        // hang up so the progress bar thread runs out of reports
+        drop(progress);
+        if let Some(progress_bar) = progress_bar {
+            progress_bar.join().unwrap();
+        }
+        if cancel.is_cancelled() {
+            println!("Stopped early.");
+        }
+        if options.stats {
+            print!("{}", stats::take());
+        }
+        if options.frames.is_none() {
+            println!("Took {:?} seconds to generate frame.", now.elapsed());
+        }
+        return;
+    }
+    unsafe {
+        CAMERA = camera;
+        DENOISE = options.denoise;
+    }
     let mut pixels = vec![];
 
     let canvas = Canvas::new(IMAGE_WIDTH as usize, IMAGE_HEIGHT as usize)
@@ -92,14 +282,16 @@ fn main() {
             if !NEED_UPDATE {
                 return;
             }
-            pixels = CAMERA.render(&world);
+            let film = CAMERA.render(&world, None, None);
+            pixels = film.image();
+            if DENOISE {
+                pixels = denoise(&pixels, &film);
+            }
             NEED_UPDATE = false;
             for (x, row) in image.chunks_mut(IMAGE_WIDTH as usize).enumerate() {
                 for (y, pixel) in row.iter_mut().enumerate() {
-                    let r = (&pixels[x][y].x * 255.999) as u8;
-                    let g = (&pixels[x][y].y * 255.999) as u8;
-                    let b = (&pixels[x][y].z * 255.999) as u8;
-                    *pixel = Color {
+                    let [r, g, b] = pixels[x][y].to_bytes();
+                    *pixel = pixel_canvas::Color {
                         r, g, b
                     }
                 }
@@ -113,6 +305,163 @@ fn main() {
     let _vector = Vec3::new(1.0, 2.0, 3.0);
 }
 
+// Renders the image, or with --frames the image sequence, to `path`.
+fn render_output(camera: &mut Camera, path: &str, options: &Options, world: &HittableList, progress: Option<&Sender<Progress>>, cancel: &CancelToken) {
+    let Some((first, last)) = options.frames else {
+        if let Some(checkpoint) = &options.checkpoint {
+            camera.checkpoint = Some(checkpoint.clone());
+            camera.resume_from = load_checkpoints(checkpoint, options, true);
+        }
+        let film = camera.render(world, progress, Some(cancel));
+        write_film(path, options, world, &film);
+        return;
+    };
+
+    let animation = turntable(Point3::new(0.0, 0.2, -1.5), 4.0, 0.5, 4.0);
+    for frame in first..=last {
+        let frame_path = frame_path(path, frame);
+        if std::path::Path::new(&frame_path).exists() {
+            println!("Skipping frame {}, {} already exists.", frame, frame_path);
+            continue;
+        }
+        let frame_start = Instant::now();
+        let time = frame as Float / options.fps;
+        camera.animate(&animation, time);
+        camera.shutter_open = time + options.shutter.0;
+        camera.shutter_close = time + options.shutter.1;
+        if let Some(checkpoint) = &options.checkpoint {
+            // frames without a checkpoint yet simply start from scratch
+            let checkpoint = crate::frame_path(checkpoint, frame);
+            camera.resume_from = load_checkpoints(&checkpoint, options, false);
+            camera.checkpoint = Some(checkpoint);
+        }
+        let film = camera.render(world, progress, Some(cancel));
+        // a partial frame would pass for a finished one when the sequence is rendered again
+        if cancel.is_cancelled() {
+            break;
+        }
+        write_film(&frame_path, options, world, &film);
+        println!("Took {:?} seconds to generate frame {}.", frame_start.elapsed(), frame);
+    }
+}
+
+// Draws the progress bar until the camera hangs up.
+fn show_progress(receiver: Receiver<Progress>) {
+    let mut last_draw: Option<Instant> = None;
+    for progress in receiver {
+        if !progress.finished && last_draw.is_some_and(|t| t.elapsed() < Duration::from_millis(100)) {
+            continue;
+        }
+        eprint!("\r{}\x1b[K", progress.bar(40));
+        if progress.finished {
+            eprintln!();
+        }
+        last_draw = Some(Instant::now());
+    }
+}
+
+// Writes the image and every pass the options ask for.
+fn write_film(path: &str, options: &Options, world: &HittableList, film: &Film) {
+    let mut pixels = film.image();
+    if options.denoise {
+        pixels = denoise(&pixels, film);
+    }
+    write_output(path, &pixels);
+    if let Some(path) = &options.sample_count_output {
+        write_output(path, &film.sample_count_image());
+    }
+    let base = path.strip_suffix(".ppm").unwrap_or(path);
+    if let Err(e) = film.write_aovs(base) {
+        eprintln!("could not write AOVs: {}", e);
+        std::process::exit(1);
+    }
+    for heatmap in &film.heatmaps {
+        let (image, top) = heatmap.image(film.width, film.height);
+        let path = format!("{}.heatmap_{}.ppm", base, heatmap.heatmap.name());
+        write_output(&path, &image);
+        println!("{}: 0 to {:.2} {}", path, top, heatmap.heatmap.unit());
+    }
+    if let Err(e) = film.write_cryptomatte(base, options.cryptomatte_ranks, &world.object_names()) {
+        eprintln!("could not write cryptomatte: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// A run of # in the output path is replaced by the zero padded frame number,
+// without one the number goes in front of the extension.
+fn frame_path(path: &str, frame: i64) -> String {
+    if let Some(start) = path.find('#') {
+        let width = path[start..].chars().take_while(|c| *c == '#').count();
+        return format!("{}{:0width$}{}", &path[..start], frame, &path[start + width..], width = width);
+    }
+    match path.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}.{:04}.{}", stem, frame, extension),
+        None => format!("{}.{:04}", path, frame),
+    }
+}
+
+// Camera circling `target` once every `period` seconds at `radius`, `height` above it.
+fn turntable(target: Point3, radius: Float, height: Float, period: Float) -> CameraAnimation {
+    let mut position = Track::new();
+    let mut forward = Track::new();
+    for key in 0..=8 {
+        let angle = key as Float / 8.0 * crate::float::consts::TAU;
+        let offset = Vec3::new(radius * angle.sin(), height, radius * angle.cos());
+        let time = key as Float / 8.0 * period;
+        position = position.key(time, target.to_vec() + offset, Interpolation::CatmullRom);
+        forward = forward.key(time, offset.normalize(), Interpolation::CatmullRom);
+    }
+    CameraAnimation { position: Some(position), forward: Some(forward) }
+}
+
+// Checkpoints to continue from with --resume, one per eye.
+fn load_checkpoints(path: &str, options: &Options, required: bool) -> Vec<Checkpoint> {
+    if !options.resume {
+        return vec![];
+    }
+    let paths = match options.stereo {
+        Some(_) => vec![format!("{}.left", path), format!("{}.right", path)],
+        None => vec![path.to_string()],
+    };
+    let mut checkpoints = vec![];
+    for path in paths {
+        if !required && !std::path::Path::new(&path).exists() {
+            continue;
+        }
+        let checkpoint = Checkpoint::load(&path).and_then(|checkpoint| {
+            checkpoint
+                .check(options.sampler, options.seed, IMAGE_WIDTH, IMAGE_HEIGHT, &options.aovs, options.cryptomatte_ranks > 0)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            Ok(checkpoint)
+        });
+        match checkpoint {
+            Ok(checkpoint) => checkpoints.push(checkpoint),
+            Err(e) => {
+                eprintln!("could not resume from {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    checkpoints
+}
+
+fn write_output(path: &str, pixels: &[Vec<Color>]) {
+    if let Err(e) = libs::write_ppm(path, pixels) {
+        eprintln!("could not write {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
+
+fn load_volume(path: &str, options: &Options, bounds: Aabb) -> std::io::Result<Volume> {
+    let density = DensityGrid::load(path)?;
+    let mut volume = Volume::new(bounds, density, options.volume_density, Color::gray(0.8))
+        .with_emission(options.volume_emission);
+    if let Some(temperature_path) = &options.volume_temperature {
+        volume = volume.with_temperature(DensityGrid::load(temperature_path)?, 1.0);
+    }
+    Ok(volume)
+}
+
 pub fn handle_input(info: &CanvasInfo, mouse: &mut MouseState, event: &Event<()>) -> bool {
     return match event {
         Event::WindowEvent {
@@ -130,6 +479,16 @@ pub fn handle_input(info: &CanvasInfo, mouse: &mut MouseState, event: &Event<()>
                                     VirtualKeyCode::F5 => unsafe {
                                         NEED_UPDATE = true;
                                     },
+                                    VirtualKeyCode::N => unsafe {
+                                        DENOISE = !DENOISE;
+                                        NEED_UPDATE = true;
+                                    },
+                                    VirtualKeyCode::M => unsafe {
+                                        let integrator = CAMERA.integrator;
+                                        CAMERA.integrator = integrator.next();
+                                        println!("integrator: {}", integrator.next().name());
+                                        NEED_UPDATE = true;
+                                    },
                                     VirtualKeyCode::A => unsafe {
                                         CAMERA.position = CAMERA.position + Vec3::new(0.1, 0.0, 0.0);
                                         NEED_UPDATE = true;
@@ -184,4 +543,18 @@ pub fn handle_input(info: &CanvasInfo, mouse: &mut MouseState, event: &Event<()>
 
         _ => { false }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_numbers_replace_the_hash_run() {
+        assert_eq!(frame_path("out/frame_###.ppm", 7), "out/frame_007.ppm");
+        assert_eq!(frame_path("#.png", 12), "12.png");
+        assert_eq!(frame_path("shot##_v#.ppm", 3), "shot03_v#.ppm");
+        assert_eq!(frame_path("render.ppm", 42), "render.0042.ppm");
+        assert_eq!(frame_path("render", 5), "render.0005");
+    }
+}
@@ -1,61 +1,235 @@
+use crate::float::{to_f64, Float};
+use crate::float::consts::PI;
 use num_traits::Pow;
-use rand::Rng;
+use crate::cryptomatte::to_float_safe;
 use crate::hittable::HitRecord;
-use crate::libs::random_unit_vector;
+use crate::libs::sample_unit_vector;
 use crate::ray::Ray;
-use crate::vec3::Vec3;
+use crate::sampler::{mix_hash, Sampler};
+use crate::color::Color;
+use crate::vec3::{Normal3, Vec3};
 
 #[derive(Clone, Copy)]
 pub enum Material {
-    Lambertian { albedo: Vec3 },
-    Metal { albedo: Vec3, fuzziness: f64 },
-    Dialectric { albedo: Vec3, refraction_index: f64 }
+    Lambertian { albedo: Color },
+    Metal { albedo: Color, fuzziness: Float },
+    // `refraction_index` is at the sodium d line, 587.6 nm, `dispersion` how it
+    // varies with wavelength when rendering spectrally
+    Dialectric { albedo: Color, refraction_index: Float, dispersion: Dispersion },
+    Isotropic { albedo: Color, emission: Color },
+    // emits on its front side and absorbs everything arriving
+    DiffuseLight { emission: Color },
 }
 
 impl Material {
-    pub fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+    pub fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, attenuation: &mut Color, scattered: &mut Ray, sampler: &mut dyn Sampler) -> bool {
         match self {
             Material::Lambertian { albedo } => {
-                let mut scatter_direction = hit_record.normal + random_unit_vector();
+                let mut scatter_direction = hit_record.normal + sample_unit_vector(sampler.get_2d());
                 if scatter_direction.near_zero() {
-                    scatter_direction = hit_record.normal
+                    scatter_direction = hit_record.normal.to_vec()
                 }
-                *scattered = Ray::new(hit_record.point, scatter_direction);
+                *scattered = Ray::new(hit_record.point, scatter_direction, ray_in.time);
                 *attenuation = *albedo;
                 true
             }
             Material::Metal { albedo, fuzziness } => {
-                let reflect_dir = ray_in.direction.reflect(&hit_record.normal).normalize() + *fuzziness * random_unit_vector();
-                *scattered = Ray::new(hit_record.point, reflect_dir);
+                let reflect_dir = ray_in.direction.reflect(&hit_record.normal).normalize() + *fuzziness * sample_unit_vector(sampler.get_2d());
+                *scattered = Ray::new(hit_record.point, reflect_dir, ray_in.time);
                 *attenuation = *albedo;
-                scattered.direction.dot(&hit_record.normal) > 0.0
+                hit_record.normal.dot(&scattered.direction) > 0.0
             }
-            Material::Dialectric { albedo, refraction_index } => {
+            Material::Dialectric { albedo, refraction_index, .. } => {
                 *attenuation = *albedo;
                 let ri = if hit_record.front_face { 1.0/refraction_index } else { *refraction_index };
                 let unit_direction = ray_in.direction.normalize();
 
-                let cos_theta = -unit_direction.dot(&hit_record.normal).min(1.0);
+                let cos_theta = -hit_record.normal.dot(&unit_direction).min(1.0);
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
                 let cannot_refract = ri * sin_theta > 1.0;
+                let reflect_sample = sampler.get_1d();
                 let mut direction = Vec3::new(0.0, 0.0, 0.0);
 
-                if cannot_refract || Self::reflectance(cos_theta, ri) > rand::thread_rng().gen_range(0.0..1.0) {
+                if cannot_refract || Self::reflectance(cos_theta, ri) > reflect_sample {
                     direction = unit_direction.reflect(&hit_record.normal);
                 } else {
                     direction = unit_direction.refract(&hit_record.normal, ri);
                 }
 
-                *scattered = Ray::new(hit_record.point, direction);
+                *scattered = Ray::new(hit_record.point, direction, ray_in.time);
                 true
             }
+            Material::Isotropic { albedo, .. } => {
+                *scattered = Ray::new(hit_record.point, sample_unit_vector(sampler.get_2d()), ray_in.time);
+                *attenuation = *albedo;
+                true
+            }
+            Material::DiffuseLight { .. } => false,
+        }
+    }
+
+    // Scattering function for light arriving from `wi` and leaving towards `wo`,
+    // `normal` faces `wo`. Specular materials can only be sampled, they return zero.
+    pub fn eval(&self, normal: Normal3, wo: Vec3, wi: Vec3) -> Color {
+        match self {
+            Material::Lambertian { albedo } if normal.dot(&wi) > 0.0 && normal.dot(&wo) > 0.0 => *albedo / PI,
+            Material::Isotropic { albedo, .. } => *albedo / (4.0 * PI),
+            _ => Color::BLACK,
+        }
+    }
+
+    // Solid angle density with which `scatter` picks `wi` after arriving from `wo`.
+    pub fn pdf(&self, normal: Normal3, _wo: Vec3, wi: Vec3) -> Float {
+        match self {
+            Material::Lambertian { .. } => normal.dot(&wi.normalize()).max(0.0) / PI,
+            Material::Isotropic { .. } => 1.0 / (4.0 * PI),
+            _ => 0.0,
+        }
+    }
+
+    pub fn albedo(&self) -> Color {
+        match self {
+            Material::Lambertian { albedo } => *albedo,
+            Material::Metal { albedo, .. } => *albedo,
+            Material::Dialectric { albedo, .. } => *albedo,
+            Material::Isotropic { albedo, .. } => *albedo,
+            Material::DiffuseLight { .. } => Color::BLACK,
+        }
+    }
+
+    pub fn emitted(&self) -> Color {
+        match self {
+            Material::Isotropic { emission, .. } | Material::DiffuseLight { emission } => *emission,
+            _ => Color::BLACK,
+        }
+    }
+
+    pub fn with_albedo(self, albedo: Color) -> Material {
+        match self {
+            Material::Lambertian { .. } => Material::Lambertian { albedo },
+            Material::Metal { fuzziness, .. } => Material::Metal { albedo, fuzziness },
+            Material::Dialectric { refraction_index, dispersion, .. } => Material::Dialectric { albedo, refraction_index, dispersion },
+            Material::Isotropic { emission, .. } => Material::Isotropic { albedo, emission },
+            Material::DiffuseLight { emission } => Material::DiffuseLight { emission },
+        }
+    }
+
+    // Sets the one scalar a material has, fuzziness for metals and the refraction
+    // index for dielectrics.
+    pub fn with_parameter(self, parameter: Float) -> Material {
+        match self {
+            Material::Metal { albedo, .. } => Material::Metal { albedo, fuzziness: parameter },
+            Material::Dialectric { albedo, dispersion, .. } => Material::Dialectric { albedo, refraction_index: parameter, dispersion },
+            other => other,
+        }
+    }
+
+    // The material as light of `lambda` nanometres sees it, dielectrics get the
+    // refraction index of that wavelength.
+    pub fn at_wavelength(self, lambda: Float) -> Material {
+        match self {
+            Material::Dialectric { albedo, refraction_index, dispersion } => {
+                Material::Dialectric { albedo, refraction_index: dispersion.refraction_index(refraction_index, lambda), dispersion: Dispersion::None }
+            }
+            other => other,
         }
     }
 
-    fn reflectance(cosine: f64, ri: f64) -> f64 {
+    // Whether light of different wavelengths leaves in different directions.
+    pub fn is_dispersive(&self) -> bool {
+        matches!(self, Material::Dialectric { dispersion, .. } if *dispersion != Dispersion::None)
+    }
+
+    // Mirrors and glass, whose reflections go to the specular pass.
+    pub fn is_specular(&self) -> bool {
+        matches!(self, Material::Metal { .. } | Material::Dialectric { .. })
+    }
+
+    // Hash of the material type and its parameters, equal materials share an id.
+    pub fn id(&self) -> u32 {
+        let (kind, albedo, parameter) = match self {
+            Material::Lambertian { albedo } => (1, *albedo, 0.0),
+            Material::Metal { albedo, fuzziness } => (2, *albedo, *fuzziness),
+            Material::Dialectric { albedo, refraction_index, .. } => (3, *albedo, *refraction_index),
+            Material::Isotropic { albedo, .. } => (4, *albedo, 0.0),
+            Material::DiffuseLight { emission } => (5, *emission, 0.0),
+        };
+        let hash = [albedo.r, albedo.g, albedo.b, parameter].iter()
+            .fold(mix_hash(kind), |h, v| mix_hash(h ^ to_f64(*v).to_bits()));
+        to_float_safe((hash >> 32) as u32)
+    }
+
+    fn reflectance(cosine: Float, ri: Float) -> Float {
         let mut r0 = (1.0 - ri) / (1.0 + ri);
         r0 = r0 * r0;
         r0 + (1.0-r0)*(1.0 - cosine).pow(5)
     }
-}
\ No newline at end of file
+}
+
+// How the refraction index of a dielectric changes with wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    None,
+    // n = a + b / λ², λ in micrometres
+    Cauchy { a: Float, b: Float },
+    // n² = 1 + Σ b λ² / (λ² - c), λ in micrometres
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Dispersion {
+    // Accepts none, water, bk7 for crown glass and sf11 for dense flint glass.
+    pub fn parse(name: &str) -> Option<Dispersion> {
+        match name {
+            "none" => Some(Dispersion::None),
+            // Abbe number around 56
+            "water" => Some(Dispersion::Cauchy { a: 1.3239, b: 0.00313 }),
+            "bk7" => Some(Dispersion::Sellmeier {
+                b: [1.03961212, 0.231792344, 1.01046945],
+                c: [0.00600069867, 0.0200179144, 103.560653],
+            }),
+            "sf11" => Some(Dispersion::Sellmeier {
+                b: [1.73759695, 0.313747346, 1.89878101],
+                c: [0.013188707, 0.0623068142, 155.23629],
+            }),
+            _ => None,
+        }
+    }
+
+    // Index at `lambda` nanometres of a material whose index at the d line is
+    // `refraction_index`, the model only gives the relative change. An index below
+    // one is that of the outside relative to the inside, as for a bubble, and
+    // changes the other way.
+    pub fn refraction_index(&self, refraction_index: Float, lambda: Float) -> Float {
+        let change = self.absolute(lambda) / self.absolute(587.6);
+        if refraction_index < 1.0 { refraction_index / change } else { refraction_index * change }
+    }
+
+    fn absolute(&self, lambda: Float) -> Float {
+        let micrometres = lambda / 1000.0;
+        let squared = micrometres * micrometres;
+        match self {
+            Dispersion::None => 1.0,
+            Dispersion::Cauchy { a, b } => a + b / squared,
+            Dispersion::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * squared / (squared - c[i])).sum::<Float>()).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bk7_matches_its_catalogue_indices() {
+        let bk7 = Dispersion::parse("bk7").unwrap();
+        // the d, F and C lines
+        assert!((bk7.absolute(587.6) - 1.5168).abs() < 1e-4);
+        assert!((bk7.absolute(486.1) - 1.5224).abs() < 1e-4);
+        assert!((bk7.absolute(656.3) - 1.5143).abs() < 1e-4);
+        assert_eq!(bk7.refraction_index(1.5168, 587.6), 1.5168);
+        assert!((bk7.refraction_index(1.5, 486.1) - 1.5 * 1.5224 / 1.5168).abs() < 1e-4);
+    }
+}
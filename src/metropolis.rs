@@ -0,0 +1,376 @@
+use crate::float::{to_f64, Float};
+use crate::float::consts::PI;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::{Features, Film};
+use crate::filter::FilterSampler;
+use crate::hittable::HittableList;
+use crate::integrator::Integrator;
+use crate::progress::{CancelToken, ProgressTracker};
+use crate::sampler::{mix_hash, Pcg32, Sampler};
+use crate::stats;
+use crate::{IMAGE_HEIGHT, IMAGE_WIDTH};
+
+// Random streams of a render, told apart so that they never overlap.
+const BOOTSTRAP_STREAM: u64 = 1;
+const CHAIN_STREAM: u64 = 2;
+const MUTATION_STREAM: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetropolisSettings {
+    // evaluate paths with the bidirectional path tracer instead of the path tracer
+    pub bidirectional: bool,
+    // Markov chains, each runs on a thread of its own; the image depends on the count
+    // but not on the machine
+    pub chains: u32,
+    // share of mutations that draw a new path instead of perturbing the current one
+    pub large_step_probability: Float,
+    // standard deviation of the perturbations in primary sample space
+    pub sigma: Float,
+    // paths drawn up front to measure the image brightness and start the chains from
+    pub bootstrap_samples: u32,
+}
+
+impl MetropolisSettings {
+    pub fn new(bidirectional: bool) -> MetropolisSettings {
+        MetropolisSettings { bidirectional, chains: 8, large_step_probability: 0.3, sigma: 0.01, bootstrap_samples: 100_000 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PrimarySample {
+    value: Float,
+    // iteration that last changed the value
+    modified: u64,
+    // value and iteration before the current mutation, for when it is rejected
+    backup: Float,
+    backup_modified: u64,
+}
+
+// Sampler whose numbers are the coordinates of a point in primary sample space,
+// which mutates from one iteration to the next. Dimensions are only touched when
+// a path asks for them, mutations not seen in between are caught up on then.
+pub struct MetropolisSampler {
+    rng: Pcg32,
+    sigma: Float,
+    large_step_probability: Float,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    dimension: usize,
+}
+
+impl MetropolisSampler {
+    // Starts with a large step, every dimension is uniformly random.
+    fn new(seed: u64, settings: &MetropolisSettings) -> MetropolisSampler {
+        MetropolisSampler {
+            rng: Pcg32::new(seed),
+            sigma: settings.sigma,
+            large_step_probability: settings.large_step_probability,
+            samples: vec![],
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_float() < self.large_step_probability;
+        self.dimension = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn next(&mut self) -> Float {
+        let index = self.dimension;
+        self.dimension += 1;
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let sample = &mut self.samples[index];
+        if sample.modified < self.last_large_step {
+            // a large step happened since this dimension was last asked for
+            sample.value = self.rng.next_float();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.next_float();
+        } else {
+            // all the small steps missed add up to one with a wider spread
+            let steps = (self.iteration - sample.modified) as Float;
+            let normal = (-2.0 * (1.0 - self.rng.next_float()).ln()).sqrt() * (2.0 * PI * self.rng.next_float()).cos();
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.modified = self.iteration;
+        sample.value
+    }
+}
+
+impl Sampler for MetropolisSampler {
+    // The point in sample space is the chain's, not the pixel's.
+    fn start_pixel_sample(&mut self, _x: u32, _y: u32, _sample_index: u32) {
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Float {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        (self.next(), self.next())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PathSample {
+    color: Color,
+    pixel: (u32, u32),
+    // how much the chains want to be here, the luminance of the color
+    importance: Float,
+    rays: u32,
+}
+
+struct Chain {
+    sampler: MetropolisSampler,
+    current: PathSample,
+    // decides which mutations are accepted
+    rng: Pcg32,
+    splats: Vec<Color>,
+}
+
+impl Chain {
+    fn splat(&mut self, pixel: (u32, u32), color: Color) {
+        let index = (pixel.1 * IMAGE_WIDTH + pixel.0) as usize;
+        self.splats[index] = self.splats[index] + color;
+    }
+}
+
+struct Render<'a> {
+    camera: &'a Camera,
+    cancel: Option<&'a CancelToken>,
+    world: &'a HittableList,
+    eye: Float,
+    settings: MetropolisSettings,
+    filter_sampler: FilterSampler,
+}
+
+impl Render<'_> {
+    fn integrator(&self) -> Box<dyn Integrator> {
+        self.camera.integrator.build(self.camera.max_bounces, self.camera.russian_roulette_depth, self.camera.seed, None, self.camera.spectral)
+    }
+
+    // Seed of the `index`th member of `stream`, different for every eye.
+    fn seed(&self, stream: u64, index: u64) -> u64 {
+        mix_hash(mix_hash(mix_hash(self.camera.seed ^ to_f64(self.eye).to_bits()) ^ stream) ^ index)
+    }
+
+    // Turns the next primary sample into a path, the first two numbers pick the pixel.
+    fn evaluate(&self, integrator: &dyn Integrator, sampler: &mut MetropolisSampler) -> PathSample {
+        let (x, y) = sampler.get_2d();
+        let pixel = (((x * IMAGE_WIDTH as Float) as u32).min(IMAGE_WIDTH - 1), ((y * IMAGE_HEIGHT as Float) as u32).min(IMAGE_HEIGHT - 1));
+        let (ray, weight) = self.camera.construct_ray(pixel.0, pixel.1, self.eye, sampler, &self.filter_sampler);
+        let Some(ray) = ray else {
+            return PathSample { color: Color::BLACK, pixel, importance: 0.0, rays: 0 };
+        };
+        let mut features = Features::default();
+        let color = integrator.li(&ray, self.world, sampler, &mut features) * weight;
+        stats::record(|s| {
+            s.primary_rays += 1;
+            s.secondary_rays += features.rays.saturating_sub(1) as u64;
+            s.paths += 1;
+            s.path_rays += features.rays as u64;
+        });
+        // negative filter lobes make negative colors, the chains still go by magnitude
+        PathSample { color, pixel, importance: color.luminance().abs(), rays: features.rays }
+    }
+
+    // Importance of every bootstrap path, spread over as many threads as there are chains.
+    fn bootstrap(&self) -> Vec<Float> {
+        let count = self.settings.bootstrap_samples as u64;
+        let threads = self.settings.chains as u64;
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads).map(|thread| {
+                scope.spawn(move || {
+                    let integrator = self.integrator();
+                    let importance = (count * thread / threads..count * (thread + 1) / threads)
+                        .map(|index| {
+                            let mut sampler = MetropolisSampler::new(self.seed(BOOTSTRAP_STREAM, index), &self.settings);
+                            self.evaluate(integrator.as_ref(), &mut sampler).importance
+                        })
+                        .collect::<Vec<Float>>();
+                    stats::flush();
+                    importance
+                })
+            }).collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
+    // Starts the chain at a bootstrap path picked in proportion to its importance,
+    // mutating on with a stream of its own.
+    fn start_chain(&self, integrator: &dyn Integrator, chain: u64, cumulative: &[Float]) -> Chain {
+        let mut rng = Pcg32::new(self.seed(CHAIN_STREAM, chain));
+        let target = rng.next_float() * cumulative[cumulative.len() - 1];
+        let index = cumulative.partition_point(|sum| *sum <= target).min(cumulative.len() - 1);
+        let mut sampler = MetropolisSampler::new(self.seed(BOOTSTRAP_STREAM, index as u64), &self.settings);
+        let current = self.evaluate(integrator, &mut sampler);
+        sampler.rng = Pcg32::new(self.seed(MUTATION_STREAM, chain));
+        Chain { sampler, current, rng, splats: vec![Color::BLACK; (IMAGE_WIDTH * IMAGE_HEIGHT) as usize] }
+    }
+
+    // Runs `mutations` steps of the chain, reporting rays every image row's worth of
+    // mutations. Returns how many steps it took before a cancel.
+    fn run_chain(&self, chain: &mut Chain, mutations: u64, rows: mpsc::Sender<u64>) -> u64 {
+        let integrator = self.integrator();
+        let mut rays = 0;
+        for step in 0..mutations {
+            if step % IMAGE_WIDTH as u64 == 0 && step > 0 {
+                let _ = rows.send(rays);
+                rays = 0;
+                if self.cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+                    stats::flush();
+                    return step;
+                }
+            }
+            chain.sampler.start_iteration();
+            let proposed = self.evaluate(integrator.as_ref(), &mut chain.sampler);
+            rays += proposed.rays as u64;
+            let current = chain.current;
+            let accept = if current.importance > 0.0 { (proposed.importance / current.importance).min(1.0) } else { 1.0 };
+            // both states get their expected share, which saves the rejected ones from
+            // being wasted
+            if proposed.importance > 0.0 {
+                chain.splat(proposed.pixel, proposed.color * (accept / proposed.importance));
+            }
+            if current.importance > 0.0 {
+                chain.splat(current.pixel, current.color * ((1.0 - accept) / current.importance));
+            }
+            if chain.rng.next_float() < accept {
+                chain.current = proposed;
+                chain.sampler.accept();
+            } else {
+                chain.sampler.reject();
+            }
+        }
+        stats::flush();
+        mutations
+    }
+}
+
+// Primary sample space Metropolis light transport for one eye, -1 left, 1 right and
+// 0 without stereo. Chains of mutated paths spend their time where the image is
+// bright, which finds light that random paths rarely reach. Every pass makes as
+// many mutations as there are pixels. Adaptive sampling and feature passes do not
+// apply, the image is all splats.
+pub fn render(camera: &Camera, world: &HittableList, eye: Float, settings: MetropolisSettings, time_limit: Option<Duration>, progress: &mut ProgressTracker, cancel: Option<&CancelToken>) -> Film {
+    let start = Instant::now();
+    let mut film = Film::new(IMAGE_WIDTH, IMAGE_HEIGHT, &[], false);
+    let pixel_count = film.pixels.len() as u64;
+    let settings = MetropolisSettings { chains: settings.chains.max(1), bootstrap_samples: settings.bootstrap_samples.max(1), ..settings };
+    let render = Render { camera, cancel, world, eye, settings, filter_sampler: FilterSampler::new(camera.filter) };
+    let rows_per_eye = camera.samples_per_pixel as u64 * IMAGE_HEIGHT as u64;
+    let first_row = if eye > 0.0 { rows_per_eye } else { 0 };
+
+    // normalization: the chains only know relative brightness, the bootstrap paths
+    // give the average
+    let mut cumulative = render.bootstrap();
+    let mut sum = 0.0;
+    for importance in cumulative.iter_mut() {
+        sum += *importance;
+        *importance = sum;
+    }
+    let brightness = sum / cumulative.len() as Float;
+    if brightness <= 0.0 {
+        progress.skip_to(first_row + rows_per_eye);
+        return film;
+    }
+    let integrator = render.integrator();
+    let mut chains: Vec<Chain> = (0..settings.chains as u64).map(|chain| render.start_chain(integrator.as_ref(), chain, &cumulative)).collect();
+    film.splats = vec![Color::BLACK; pixel_count as usize];
+
+    let chain_count = chains.len() as u64;
+    for sample in 0..camera.samples_per_pixel {
+        let (sender, receiver) = mpsc::channel();
+        let mutations: u64 = thread::scope(|scope| {
+            let render = &render;
+            let handles: Vec<_> = chains.iter_mut().enumerate().map(|(index, chain)| {
+                let index = index as u64;
+                let mutations = pixel_count * (index + 1) / chain_count - pixel_count * index / chain_count;
+                let sender = sender.clone();
+                scope.spawn(move || render.run_chain(chain, mutations, sender))
+            }).collect();
+            drop(sender);
+            for rays in receiver {
+                progress.row_finished(rays);
+            }
+            handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+        });
+
+        // merged in chain order, so the sums come out the same on every run
+        for chain in &mut chains {
+            for (splat, chain_splat) in film.splats.iter_mut().zip(chain.splats.iter_mut()) {
+                *splat = *splat + *chain_splat * brightness;
+                *chain_splat = Color::BLACK;
+            }
+        }
+        film.light_paths += mutations;
+        progress.skip_to(first_row + (sample as u64 + 1) * IMAGE_HEIGHT as u64);
+
+        let cancelled = cancel.is_some_and(|cancel| cancel.is_cancelled());
+        if cancelled || time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+            break;
+        }
+    }
+    film
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::integrator::IntegratorKind;
+    use crate::material::Material;
+    use crate::vec3::Point3;
+
+    fn render(seed: u64, chains: u32) -> Vec<Vec<Color>> {
+        let mut world = HittableList::new();
+        let ground = Material::Lambertian { albedo: Color::new(0.5, 0.5, 0.5) };
+        world.push_named("ground", Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, Some(ground))));
+        world.push_named("ball", Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, Some(ground))));
+        world.build_bvh();
+        let mut camera = Camera::new(Point3::ORIGIN, 1.0, 3, 1, 90.0);
+        camera.seed = seed;
+        camera.integrator = IntegratorKind::Metropolis(MetropolisSettings { chains, bootstrap_samples: 1000, ..MetropolisSettings::new(false) });
+        camera.render(&world, None, None).image()
+    }
+
+    #[test]
+    fn same_seed_and_chain_count_give_the_same_image() {
+        let image = render(1, 2);
+        assert!(image == render(1, 2));
+        assert!(image != render(2, 2));
+    }
+}
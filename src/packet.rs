@@ -0,0 +1,157 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::ray::Ray;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+pub use crate::simd::Float4;
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+pub use scalar::Float4;
+
+// Packet sizes the camera traces coherent rays in.
+pub const PACKET_SIZES: [u32; 3] = [4, 8, 16];
+
+// Four lanes worked through one after another, for builds without the `simd` feature.
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+mod scalar {
+    use std::ops::{Add, Div, Mul, Sub};
+    use crate::float::Float;
+
+    #[derive(Clone, Copy)]
+    pub struct Float4([Float; 4]);
+
+    impl Float4 {
+        pub fn from_array(v: [Float; 4]) -> Float4 {
+            Float4(v)
+        }
+
+        pub fn splat(v: Float) -> Float4 {
+            Float4([v; 4])
+        }
+
+        pub fn to_array(self) -> [Float; 4] {
+            self.0
+        }
+
+        pub fn sqrt(self) -> Float4 {
+            Float4(self.0.map(Float::sqrt))
+        }
+
+        // Per lane `if self > other { self } else { other }`, a NaN in self gives other.
+        pub fn max(self, other: Float4) -> Float4 {
+            Float4(std::array::from_fn(|i| if self.0[i] > other.0[i] { self.0[i] } else { other.0[i] }))
+        }
+
+        pub fn min(self, other: Float4) -> Float4 {
+            Float4(std::array::from_fn(|i| if self.0[i] < other.0[i] { self.0[i] } else { other.0[i] }))
+        }
+
+        // Lanes of `a` where self is negative, of `b` elsewhere.
+        pub fn select_negative(self, a: Float4, b: Float4) -> Float4 {
+            Float4(std::array::from_fn(|i| if self.0[i] < 0.0 { a.0[i] } else { b.0[i] }))
+        }
+    }
+
+    macro_rules! float4_op {
+        ($trait:ident, $fn:ident, $op:tt) => {
+            impl $trait for Float4 {
+                type Output = Float4;
+
+                fn $fn(self, rhs: Float4) -> Float4 {
+                    Float4(std::array::from_fn(|i| self.0[i] $op rhs.0[i]))
+                }
+            }
+        };
+    }
+
+    float4_op!(Add, add, +);
+    float4_op!(Sub, sub, -);
+    float4_op!(Mul, mul, *);
+    float4_op!(Div, div, /);
+}
+
+// Four rays of a packet with their components side by side, one ray per lane.
+pub struct RayChunk {
+    pub origin: [Float4; 3],
+    pub direction: [Float4; 3],
+    inv_direction: [Float4; 3],
+    pub time: [Float; 4],
+}
+
+// Rays that point the same way along every axis, traced through the BVH together.
+// Lane masks have a bit per ray. Packets that do not fill their last chunk repeat
+// their last ray in the spare lanes, which never take part. Spheres are the only
+// primitive the scenes have, there are no triangles, so they are the only objects
+// with a packet test, volumes and everything else are tested ray by ray.
+pub struct RayPacket {
+    pub rays: Vec<Ray>,
+    chunks: Vec<RayChunk>,
+    negative: [bool; 3],
+}
+
+impl RayPacket {
+    // None unless the rays agree on the sign of their direction along every axis,
+    // only then do they visit BVH nodes in the same order as on their own.
+    pub fn new(rays: &[Ray]) -> Option<RayPacket> {
+        let first = rays.first()?;
+        let negative = [0, 1, 2].map(|axis| first.direction[axis] < 0.0);
+        if rays.len() > 32 || rays.iter().any(|ray| (0..3).any(|axis| (ray.direction[axis] < 0.0) != negative[axis])) {
+            return None;
+        }
+        let chunks = rays.chunks(4).map(|chunk| {
+            let lanes: [Ray; 4] = std::array::from_fn(|i| chunk[i.min(chunk.len() - 1)]);
+            let direction = [0, 1, 2].map(|axis| Float4::from_array(lanes.map(|ray| ray.direction[axis])));
+            RayChunk {
+                origin: [0, 1, 2].map(|axis| Float4::from_array(lanes.map(|ray| ray.origin[axis]))),
+                direction,
+                inv_direction: direction.map(|d| Float4::splat(1.0) / d),
+                time: lanes.map(|ray| ray.time),
+            }
+        }).collect();
+        Some(RayPacket { rays: rays.to_vec(), chunks, negative })
+    }
+
+    pub fn chunks(&self) -> &[RayChunk] {
+        &self.chunks
+    }
+
+    // Rays plus spare lanes, the length of per lane arrays.
+    pub fn lanes(&self) -> usize {
+        self.chunks.len() * 4
+    }
+
+    pub fn all_lanes(&self) -> u32 {
+        ((1u64 << self.rays.len()) - 1) as u32
+    }
+
+    // Whether the rays point the negative way along `axis`.
+    pub fn negative(&self, axis: usize) -> bool {
+        self.negative[axis]
+    }
+
+    // Those of `lanes` whose ray passes through `bounds` between `ray_t_min` and its
+    // closest hit, the slab test of `Aabb::hit` for four rays at a time.
+    pub fn hit_bounds(&self, bounds: &Aabb, lanes: u32, ray_t_min: Float, closest: &[Float]) -> u32 {
+        let mut hits = 0;
+        for (c, chunk) in self.chunks.iter().enumerate() {
+            let chunk_lanes = (lanes >> (4 * c)) & 0xf;
+            if chunk_lanes == 0 {
+                continue;
+            }
+            let mut t0 = Float4::splat(ray_t_min);
+            let mut t1 = Float4::from_array(closest[4 * c..4 * c + 4].try_into().unwrap());
+            for axis in 0..3 {
+                let inv_d = chunk.inv_direction[axis];
+                let to_min = (Float4::splat(bounds.min[axis]) - chunk.origin[axis]) * inv_d;
+                let to_max = (Float4::splat(bounds.max[axis]) - chunk.origin[axis]) * inv_d;
+                t0 = inv_d.select_negative(to_max, to_min).max(t0);
+                t1 = inv_d.select_negative(to_min, to_max).min(t1);
+            }
+            let (t0, t1) = (t0.to_array(), t1.to_array());
+            for lane in 0..4 {
+                if chunk_lanes & (1 << lane) != 0 && t1[lane] > t0[lane] {
+                    hits |= 1 << (4 * c + lane);
+                }
+            }
+        }
+        hits
+    }
+}
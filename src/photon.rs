@@ -0,0 +1,204 @@
+use crate::float::Float;
+use crate::float::consts::PI;
+use crate::color::Color;
+use crate::film::Features;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::integrator::{sky_color, Integrator};
+use crate::kdtree::KdTree;
+use crate::libs::sample_unit_vector;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::stats;
+use crate::vec3::{Point3, Vec3};
+
+// How fast the gather radius shrinks, between 0 and 1. Larger values shrink it more
+// slowly, so there is less noise but the blur lasts longer.
+const ALPHA: Float = 2.0 / 3.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Photon {
+    // unit direction back to where the photon came from
+    direction: Vec3,
+    power: Color,
+}
+
+// Progressive photon mapping in the form of Knaus and Zwicker: every pass traces a
+// new photon map from the scene lights and gathers it with a radius that shrinks
+// from pass to pass, so the average over passes converges. Camera paths follow
+// glass, mirrors and volumes to the first diffuse surface and estimate the light
+// from scene lights there, caustics included, from the photon density. The sky and
+// other emitters are path traced on from that surface.
+pub struct PhotonMappingIntegrator {
+    pub max_bounces: u32,
+    pub russian_roulette_depth: u32,
+    // photons emitted per pass
+    pub photons: u32,
+    // gather radius of the first pass
+    pub radius: Float,
+    pub seed: u64,
+    map: KdTree<Photon>,
+    pass_radius: Float,
+}
+
+impl PhotonMappingIntegrator {
+    pub fn new(max_bounces: u32, russian_roulette_depth: u32, photons: u32, radius: Float, seed: u64) -> PhotonMappingIntegrator {
+        PhotonMappingIntegrator { max_bounces, russian_roulette_depth, photons, radius, seed, map: KdTree::new(vec![]), pass_radius: radius }
+    }
+
+    // Follows one photon from a light, leaving a copy on every diffuse surface it hits.
+    fn trace_photon(&self, world: &HittableList, time: Float, sampler: &mut dyn Sampler, photons: &mut Vec<(Point3, Photon)>) {
+        let count = world.lights.len();
+        let light = world.lights[((sampler.get_1d() * count as Float) as usize).min(count - 1)];
+        let (point, normal) = light.sample_point(sampler.get_2d());
+        let mut direction = normal + sample_unit_vector(sampler.get_2d());
+        if direction.length_squared() < 1e-12 {
+            direction = normal.to_vec();
+        }
+        // emitted cosine weighted, the cosine cancels against the density of the direction
+        let mut power = light.emission * (PI * light.area() * count as Float);
+        let mut ray = Ray::new(point, direction.normalize(), time);
+
+        for depth in 0..self.max_bounces {
+            let mut hit = HitRecord::empty();
+            if !world.hit(&ray, 0.001, Float::INFINITY, &mut hit) {
+                break;
+            }
+            let Some(material) = hit.material else {
+                break;
+            };
+            if gathers(&material) {
+                photons.push((hit.point, Photon { direction: -ray.direction.normalize(), power }));
+            }
+            let mut scattered = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, 0.0), time);
+            let mut attenuation = Color::BLACK;
+            if !material.scatter(&ray, &hit, &mut attenuation, &mut scattered, sampler) {
+                break;
+            }
+            if depth + 1 >= self.russian_roulette_depth {
+                let survival = attenuation.max_component().min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                attenuation = attenuation / survival;
+            }
+            power = power * attenuation;
+            ray = scattered;
+        }
+    }
+
+    // Radiance towards `wo` from the photons around the hit.
+    fn gather(&self, hit: &HitRecord, material: &Material, wo: Vec3) -> Color {
+        let mut sum = Color::BLACK;
+        self.map.for_each_within(hit.point, self.pass_radius, |photon, _| {
+            sum = sum + material.eval(hit.normal, wo, photon.direction) * photon.power;
+        });
+        sum / (self.photons.max(1) as Float * PI * self.pass_radius * self.pass_radius)
+    }
+}
+
+// Surfaces photons are stored on and gathered at.
+fn gathers(material: &Material) -> bool {
+    matches!(material, Material::Lambertian { .. })
+}
+
+impl Integrator for PhotonMappingIntegrator {
+    fn li(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler, features: &mut Features) -> Color {
+        let mut radiance = Color::BLACK;
+        let mut throughput = Color::WHITE;
+        let mut ray = *ray;
+        // after the gather the light of scene lights is counted, hitting them adds nothing
+        let mut gathered = false;
+
+        for depth in 0..self.max_bounces {
+            let mut hit = HitRecord::empty();
+            let rays = features.rays + 1;
+            if !world.hit(&ray, 0.001, Float::INFINITY, &mut hit) {
+                let sky = sky_color(&ray);
+                if depth == 0 {
+                    // the sky is its own albedo, as for the path tracer
+                    *features = Features { albedo: sky, emission: sky, ..Features::default() };
+                }
+                features.rays = rays;
+                return radiance + throughput * sky;
+            }
+            if depth == 0 {
+                *features = Features::first_hit(&ray, &hit);
+            }
+            features.rays = rays;
+            let Some(material) = hit.material else {
+                break;
+            };
+
+            if !(gathered && matches!(material, Material::DiffuseLight { .. })) {
+                radiance = radiance + throughput * material.emitted();
+            }
+            if !gathered && gathers(&material) {
+                radiance = radiance + throughput * self.gather(&hit, &material, -ray.direction.normalize());
+                gathered = true;
+            }
+
+            let mut scattered = Ray::new(Point3::ORIGIN, Vec3::new(0.0, 0.0, 0.0), ray.time);
+            let mut attenuation = Color::BLACK;
+            if !material.scatter(&ray, &hit, &mut attenuation, &mut scattered, sampler) {
+                break;
+            }
+            let mut survival = 1.0;
+            if depth + 1 >= self.russian_roulette_depth {
+                survival = (throughput * attenuation).max_component().min(0.95);
+                if sampler.get_1d() >= survival {
+                    stats::record(|s| s.russian_roulette_kills += 1);
+                    break;
+                }
+            }
+            throughput = throughput * attenuation / survival;
+            ray = scattered;
+            if depth + 1 == self.max_bounces {
+                stats::record(|s| s.max_bounce_kills += 1);
+            }
+        }
+        radiance
+    }
+
+    // Traces the photons of the pass, seen at `time`, so caustics do not blur with motion.
+    fn start_pass(&mut self, world: &HittableList, pass: u32, time: Float) {
+        let mut radius_squared = self.radius * self.radius;
+        for k in 1..=pass {
+            radius_squared *= (k as Float + ALPHA) / (k as Float + 1.0);
+        }
+        self.pass_radius = radius_squared.sqrt();
+
+        let mut photons = vec![];
+        if !world.lights.is_empty() {
+            let mut sampler = SamplerKind::Independent.build(1, self.seed);
+            for index in 0..self.photons {
+                // every photon gets a sample stream of its own, apart from the pixels'
+                sampler.start_pixel_sample(index, u32::MAX, pass);
+                self.trace_photon(world, time, sampler.as_mut(), &mut photons);
+            }
+        }
+        self.map = KdTree::new(photons);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gather_radius_follows_knaus_zwicker() {
+        let world = HittableList::new();
+        let mut integrator = PhotonMappingIntegrator::new(4, 3, 0, 0.1, 0);
+        let mut previous = Float::INFINITY;
+        for pass in [0, 1, 2, 10, 100, 10_000] {
+            integrator.start_pass(&world, pass, 0.0);
+            assert!(integrator.pass_radius < previous);
+            previous = integrator.pass_radius;
+        }
+        // the squared radius falls off as pass^(alpha - 1) / gamma(1 + alpha), and
+        // gamma(5 / 3) is 0.9027
+        let ratio = previous * previous / (0.1 * 0.1);
+        let expected = Float::powf(10_000.0, ALPHA - 1.0) / 0.9027;
+        assert!((ratio / expected - 1.0).abs() < 0.01, "{} against {}", ratio, expected);
+    }
+}
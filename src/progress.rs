@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Snapshot of a running render, sent after every finished row.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    // rows finished over all passes and eyes, including those of a resumed checkpoint
+    pub rows_done: u64,
+    // share of the render done, time limited renders count their time too
+    pub fraction: f64,
+    pub rays: u64,
+    pub rays_per_second: f64,
+    pub elapsed: Duration,
+    pub eta: Option<Duration>,
+    pub finished: bool,
+}
+
+impl Progress {
+    // One line progress bar `width` characters wide, without a line break.
+    pub fn bar(&self, width: usize) -> String {
+        let filled = ((self.fraction * width as f64) as usize).min(width);
+        let time = match self.eta {
+            Some(eta) if !self.finished => format!("ETA {}", format_duration(eta)),
+            _ => format!("took {} for {:.1} Mrays", format_duration(self.elapsed), self.rays as f64 / 1e6),
+        };
+        format!(
+            "[{}{}] {:5.1}% {:>8} rows {:7.2} Mrays/s {}",
+            "#".repeat(filled),
+            "-".repeat(width - filled),
+            self.fraction * 100.0,
+            self.rows_done,
+            self.rays_per_second / 1e6,
+            time
+        )
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+// Shared flag that asks a render to stop after the row it is working on. The
+// render then returns the image as far as it got.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Counts the work of one render and turns it into Progress reports.
+pub struct ProgressTracker<'a> {
+    sender: Option<&'a Sender<Progress>>,
+    start: Instant,
+    time_limit: Option<Duration>,
+    rows: u64,
+    rows_done: u64,
+    // rows rendered by this run, what the speed and ETA are based on
+    rows_rendered: u64,
+    rays: u64,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub fn new(sender: Option<&'a Sender<Progress>>, rows: u64, time_limit: Option<Duration>) -> ProgressTracker<'a> {
+        ProgressTracker { sender, start: Instant::now(), time_limit, rows, rows_done: 0, rows_rendered: 0, rays: 0 }
+    }
+
+    // Rows a resumed checkpoint already holds.
+    pub fn skip_rows(&mut self, rows: u64) {
+        self.rows_done += rows;
+    }
+
+    pub fn row_finished(&mut self, rays: u64) {
+        self.rows_done += 1;
+        self.rows_rendered += 1;
+        self.rays += rays;
+        self.send(false);
+    }
+
+    // Moves on to `rows_done` when a part of the render ends early, say because
+    // every pixel converged.
+    pub fn skip_to(&mut self, rows_done: u64) {
+        self.rows_done = self.rows_done.max(rows_done);
+    }
+
+    pub fn finish(&mut self) {
+        self.send(true);
+    }
+
+    fn send(&self, finished: bool) {
+        let Some(sender) = self.sender else {
+            return;
+        };
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs_f64();
+        let mut fraction = self.rows_done as f64 / self.rows as f64;
+        let mut eta = (self.rows_rendered > 0)
+            .then(|| Duration::from_secs_f64(seconds * (self.rows - self.rows_done) as f64 / self.rows_rendered as f64));
+        if let Some(limit) = self.time_limit {
+            fraction = fraction.max(seconds / limit.as_secs_f64());
+            let remaining = limit.saturating_sub(elapsed);
+            eta = Some(eta.map_or(remaining, |eta| eta.min(remaining)));
+        }
+        let progress = Progress {
+            rows_done: self.rows_done,
+            fraction: fraction.min(1.0),
+            rays: self.rays,
+            rays_per_second: if seconds > 0.0 { self.rays as f64 / seconds } else { 0.0 },
+            elapsed,
+            eta,
+            finished,
+        };
+        // nobody listening is fine
+        let _ = sender.send(progress);
+    }
+}
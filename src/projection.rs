@@ -0,0 +1,224 @@
+use crate::float::Float;
+use crate::float::consts::{FRAC_PI_2, PI};
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    // image radius grows linearly with the angle off the view axis
+    Equidistant,
+    // equal image areas cover equal solid angles
+    Equisolid,
+}
+
+// How image positions map to camera rays.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    // parallel rays over a view `view_width` scene units wide
+    Orthographic { view_width: Float },
+    // circular image filling the shorter image side, `fov` degrees across
+    Fisheye { mapping: FisheyeMapping, fov: Float },
+    // 360 degrees around, perspective up and down
+    Cylindrical,
+    // 360 degrees around and 180 degrees up and down
+    Equirectangular,
+}
+
+impl Projection {
+    // Accepts perspective, orthographic[:width], fisheye-equidistant[:fov],
+    // fisheye-equisolid[:fov], cylindrical and equirectangular.
+    pub fn parse(name: &str) -> Option<Projection> {
+        let (kind, parameter) = match name.split_once(':') {
+            Some((kind, parameter)) => (kind, Some(parameter.parse::<Float>().ok().filter(|p| *p > 0.0 && p.is_finite())?)),
+            None => (name, None),
+        };
+        // a fisheye sees at most all the way around
+        let fov = match parameter {
+            Some(fov) if fov > 360.0 => return None,
+            fov => fov.unwrap_or(180.0),
+        };
+        match kind {
+            "orthographic" => Some(Projection::Orthographic { view_width: parameter.unwrap_or(4.0) }),
+            "fisheye-equidistant" => Some(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov }),
+            "fisheye-equisolid" => Some(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov }),
+            _ if parameter.is_some() => None,
+            "perspective" => Some(Projection::Perspective),
+            "cylindrical" => Some(Projection::Cylindrical),
+            "equirectangular" => Some(Projection::Equirectangular),
+            _ => None,
+        }
+    }
+
+    // Ray origin offset and direction in camera space (x right, y up, looking down -z)
+    // for an image position given in [-1, 1] on both axes, with y pointing up. None
+    // for positions that see nothing, like the corners around a fisheye circle.
+    // Depth of field is left to the camera.
+    pub fn camera_ray(&self, x: Float, y: Float, aspect: Float, vfov: Float) -> Option<(Vec3, Vec3)> {
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        match *self {
+            Projection::Perspective => {
+                let h = (vfov.to_radians() / 2.0).tan();
+                Some((origin, Vec3::new(x * h * aspect, y * h, -1.0)))
+            }
+            Projection::Orthographic { view_width } => {
+                let offset = Vec3::new(x * view_width / 2.0, y * view_width / aspect / 2.0, 0.0);
+                Some((offset, Vec3::new(0.0, 0.0, -1.0)))
+            }
+            Projection::Fisheye { mapping, fov } => {
+                // scale so the circle touches the shorter image side
+                let (px, py) = if aspect >= 1.0 { (x * aspect, y) } else { (x, y / aspect) };
+                let r = (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let max_theta = (fov / 2.0).to_radians();
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (max_theta / 2.0).sin()).asin(),
+                };
+                let (sin_phi, cos_phi) = if r > 0.0 { (py / r, px / r) } else { (0.0, 0.0) };
+                Some((origin, Vec3::new(theta.sin() * cos_phi, theta.sin() * sin_phi, -theta.cos())))
+            }
+            Projection::Cylindrical => {
+                let phi = x * PI;
+                let height = y * (vfov.to_radians() / 2.0).tan();
+                Some((origin, Vec3::new(phi.sin(), height, -phi.cos())))
+            }
+            Projection::Equirectangular => {
+                let phi = x * PI;
+                let latitude = y * FRAC_PI_2;
+                Some((origin, Vec3::new(latitude.cos() * phi.sin(), latitude.sin(), -latitude.cos() * phi.cos())))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    // left eye above the right one
+    TopBottom,
+    // left eye to the left of the right one
+    SideBySide,
+}
+
+// Two eye views `ipd` apart whose rays converge at `convergence` scene units.
+// Panoramic projections use omni-directional stereo: every ray starts from the
+// point on the eye circle that sits beside its own viewing direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stereo {
+    pub ipd: Float,
+    pub convergence: Float,
+    pub layout: StereoLayout,
+}
+
+impl StereoLayout {
+    pub fn parse(name: &str) -> Option<StereoLayout> {
+        match name {
+            "top-bottom" => Some(StereoLayout::TopBottom),
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            _ => None,
+        }
+    }
+}
+
+impl Stereo {
+    // Moves a camera space ray of `projection` to one eye, -1 for the left eye
+    // and 1 for the right one.
+    pub fn eye_ray(&self, projection: Projection, offset: Vec3, direction: Vec3, eye: Float) -> (Vec3, Vec3) {
+        let half = eye * self.ipd / 2.0;
+        let eye_position = match projection {
+            Projection::Cylindrical | Projection::Equirectangular => {
+                // to the right of the horizontal viewing direction, nothing at the poles
+                let horizontal = (direction.x * direction.x + direction.z * direction.z).sqrt();
+                if horizontal < 1e-9 {
+                    return (offset, direction);
+                }
+                Vec3::new(-direction.z, 0.0, direction.x) * (half / horizontal)
+            }
+            _ => Vec3::new(half, 0.0, 0.0),
+        };
+
+        let direction = match projection {
+            Projection::Orthographic { .. } => direction,
+            Projection::Perspective if direction.z < 0.0 => {
+                // off-axis: meet the centre eye ray on the convergence plane, no toe-in
+                direction * (self.convergence / -direction.z) - eye_position
+            }
+            _ => direction.normalize() * self.convergence - eye_position,
+        };
+        (offset + eye_position, direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_parallel(a: Vec3, b: Vec3) {
+        assert!((a.normalize() - b.normalize()).length() < 1e-4, "{:?} is not along {:?}", a, b);
+    }
+
+    #[test]
+    fn parse_rejects_bad_parameters() {
+        assert_eq!(Projection::parse("orthographic:2"), Some(Projection::Orthographic { view_width: 2.0 }));
+        assert_eq!(Projection::parse("fisheye-equisolid:360"), Some(Projection::Fisheye { mapping: FisheyeMapping::Equisolid, fov: 360.0 }));
+        assert_eq!(Projection::parse("fisheye-equidistant"), Some(Projection::Fisheye { mapping: FisheyeMapping::Equidistant, fov: 180.0 }));
+        for name in ["orthographic:0", "orthographic:-4", "orthographic:inf", "fisheye-equidistant:0", "fisheye-equisolid:361", "perspective:90", "cylindrical:1"] {
+            assert_eq!(Projection::parse(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn centre_looks_straight_ahead() {
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        for name in ["perspective", "orthographic", "fisheye-equidistant", "fisheye-equisolid:360", "cylindrical", "equirectangular"] {
+            let (_, direction) = Projection::parse(name).unwrap().camera_ray(0.0, 0.0, 1.4, 90.0).unwrap();
+            assert_parallel(direction, forward);
+        }
+    }
+
+    #[test]
+    fn equirectangular_edges_wrap_around() {
+        for y in [-0.9, -0.3, 0.0, 0.5] {
+            let (_, left) = Projection::Equirectangular.camera_ray(-1.0, y, 2.0, 90.0).unwrap();
+            let (_, right) = Projection::Equirectangular.camera_ray(1.0, y, 2.0, 90.0).unwrap();
+            assert_parallel(left, right);
+            // both edges look backwards
+            assert!(left.z > 0.0);
+        }
+    }
+
+    const STEREO: Stereo = Stereo { ipd: 0.064, convergence: 3.0, layout: StereoLayout::SideBySide };
+
+    #[test]
+    fn perspective_eye_rays_meet_at_the_convergence_plane() {
+        for (x, y) in [(0.0, 0.0), (0.8, -0.3), (-0.5, 0.9)] {
+            let (offset, direction) = Projection::Perspective.camera_ray(x, y, 1.4, 60.0).unwrap();
+            let (left_origin, left) = STEREO.eye_ray(Projection::Perspective, offset, direction, -1.0);
+            let (right_origin, right) = STEREO.eye_ray(Projection::Perspective, offset, direction, 1.0);
+            let (left_end, right_end) = (left_origin + left, right_origin + right);
+            assert!((left_end - right_end).length() < 1e-5, "{:?} and {:?}", left_end, right_end);
+            assert!((left_end.z + STEREO.convergence).abs() < 1e-5);
+            // both still look through the same point the centre ray does
+            assert_parallel(left_end, direction);
+        }
+    }
+
+    #[test]
+    fn panoramic_eyes_sit_beside_the_viewing_direction() {
+        for projection in [Projection::Equirectangular, Projection::Cylindrical] {
+            for (x, y) in [(0.0, 0.0), (0.5, 0.2), (-0.9, -0.6), (1.0, 0.4)] {
+                let (offset, direction) = projection.camera_ray(x, y, 2.0, 90.0).unwrap();
+                let horizontal = Vec3::new(direction.x, 0.0, direction.z);
+                let (left, _) = STEREO.eye_ray(projection, offset, direction, -1.0);
+                let (right, _) = STEREO.eye_ray(projection, offset, direction, 1.0);
+                for eye in [left, right] {
+                    assert!(eye.dot(&horizontal).abs() < 1e-6 && eye.y == 0.0, "{:?} for {:?}", eye, direction);
+                    assert!((eye.length() - STEREO.ipd / 2.0).abs() < 1e-6);
+                }
+                assert!((left + right).length() < 1e-6);
+            }
+        }
+    }
+}
@@ -1,17 +1,21 @@
+use crate::float::Float;
 use crate::vec3::Vec3;
 use crate::vec3::Point3;
 
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     pub origin: Point3,
-    pub direction: Vec3
+    pub direction: Vec3,
+    // moment within the shutter interval the ray samples, in seconds
+    pub time: Float,
 }
 
 impl Ray {
-    pub fn at(&self, t: f64) -> Point3 {
+    pub fn at(&self, t: Float) -> Point3 {
         return self.origin + self.direction * t;
     }
 
-    pub fn new(origin: Point3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+    pub fn new(origin: Point3, direction: Vec3, time: Float) -> Ray {
+        Ray { origin, direction, time }
     }
 }
\ No newline at end of file
@@ -0,0 +1,367 @@
+// Per pixel sample generators. A sampler is positioned on a (pixel, sample index)
+// with `start_pixel_sample` and then hands out consecutive dimensions of that sample.
+// Every sampler is a pure function of pixel, sample index, dimension and seed, so a
+// render is reproducible no matter which thread renders which pixel.
+
+use crate::float::{Float, ONE_MINUS_EPSILON};
+
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+    fn get_1d(&mut self) -> Float;
+    fn get_2d(&mut self) -> (Float, Float);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn parse(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        }
+    }
+
+    pub fn build(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        let state = SampleState { seed, pixel: 0, index: 0, dimension: 0 };
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { state, rng: Pcg32::new(0) }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler { state, samples_per_pixel: samples_per_pixel.max(1) }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+// Small PCG32 generator, used where a stream of plain random numbers is enough.
+#[derive(Clone, Copy, Debug)]
+pub struct Pcg32 {
+    state: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(Self::INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    pub fn next_float(&mut self) -> Float {
+        to_unit(self.next_u32())
+    }
+}
+
+pub fn mix_hash(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+fn hash_combine(a: u64, b: u64) -> u64 {
+    mix_hash(a ^ b.wrapping_add(0x9e3779b97f4a7c15).wrapping_add(a << 6).wrapping_add(a >> 2))
+}
+
+fn to_unit(bits: u32) -> Float {
+    ((bits as f64 / 4294967296.0) as Float).min(ONE_MINUS_EPSILON)
+}
+
+struct SampleState {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = ((x as u64) << 32) | y as u64;
+        self.index = sample_index;
+        self.dimension = 0;
+    }
+
+    // Hash that stays the same for every sample of a pixel in the current dimension.
+    fn dimension_hash(&self) -> u64 {
+        hash_combine(hash_combine(self.seed, self.pixel), self.dimension as u64)
+    }
+
+    fn advance(&mut self, dimensions: u32) -> u64 {
+        let hash = self.dimension_hash();
+        self.dimension += dimensions;
+        hash
+    }
+}
+
+pub struct IndependentSampler {
+    state: SampleState,
+    rng: Pcg32,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+        self.rng = Pcg32::new(hash_combine(self.state.dimension_hash(), sample_index as u64));
+    }
+
+    fn get_1d(&mut self) -> Float {
+        self.rng.next_float()
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        (self.rng.next_float(), self.rng.next_float())
+    }
+}
+
+// Jittered sampling: every dimension gets its own random permutation of the strata,
+// so consecutive samples of a pixel cover the domain evenly.
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    fn jitter(&self, hash: u64, salt: u64) -> Float {
+        to_unit(mix_hash(hash_combine(hash, ((self.state.index as u64) << 8) | salt)) as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let hash = self.state.advance(1);
+        let n = self.samples_per_pixel;
+        let stratum = permutation_element(self.state.index % n, n, hash as u32);
+        ((stratum as Float + self.jitter(hash, 0)) / n as Float).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        let hash = self.state.advance(2);
+        let nx = (self.samples_per_pixel as Float).sqrt().floor().max(1.0) as u32;
+        let ny = self.samples_per_pixel / nx;
+        let stratum = permutation_element(self.state.index % (nx * ny), nx * ny, hash as u32);
+        (
+            (((stratum % nx) as Float + self.jitter(hash, 0)) / nx as Float).min(ONE_MINUS_EPSILON),
+            (((stratum / nx) as Float + self.jitter(hash, 1)) / ny as Float).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+// Kensler's hashed permutation, returns element i of a random permutation of 0..n.
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    if n <= 1 {
+        return 0;
+    }
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+// Halton sequence indexed by sample number, decorrelated between pixels with a
+// per pixel Cranley-Patterson rotation. Dimensions past the prime table fall back
+// to hashed random numbers.
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    fn sample(&mut self) -> Float {
+        let dimension = self.state.dimension as usize;
+        let hash = self.state.advance(1);
+        let offset = to_unit(hash as u32);
+        if dimension >= PRIMES.len() {
+            return to_unit(mix_hash(hash_combine(hash, self.state.index as u64)) as u32);
+        }
+        let value = radical_inverse(self.state.index, PRIMES[dimension]) + offset;
+        if value >= 1.0 { value - 1.0 } else { value }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        self.sample()
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        (self.sample(), self.sample())
+    }
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> Float {
+    let inv_base = 1.0 / base as Float;
+    let mut inv_base_n = 1.0;
+    let mut reversed: u64 = 0;
+    while index > 0 {
+        let next = index / base;
+        reversed = reversed * base as u64 + (index - next * base) as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed as Float * inv_base_n).min(ONE_MINUS_EPSILON)
+}
+
+// Owen scrambled Sobol (0,2) sequence with hashed index shuffling and per dimension
+// padding, following Burley's "Practical Hash-based Owen Scrambling".
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    fn shuffled_index(&self, hash: u64) -> u32 {
+        nested_uniform_scramble(self.state.index, hash as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.state.start(x, y, sample_index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let hash = self.state.advance(1);
+        let index = self.shuffled_index(hash);
+        to_unit(nested_uniform_scramble(index.reverse_bits(), (hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (Float, Float) {
+        let hash = self.state.advance(2);
+        let index = self.shuffled_index(hash);
+        let seed = mix_hash(hash);
+        (
+            to_unit(nested_uniform_scramble(index.reverse_bits(), seed as u32)),
+            to_unit(nested_uniform_scramble(sobol_second_dimension(index), (seed >> 32) as u32)),
+        )
+    }
+}
+
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+    fn dimensions(sampler: &mut dyn Sampler, x: u32, y: u32, sample_index: u32) -> Vec<Float> {
+        sampler.start_pixel_sample(x, y, sample_index);
+        let mut values = vec![];
+        for _ in 0..6 {
+            values.push(sampler.get_1d());
+            let (u, v) = sampler.get_2d();
+            values.extend([u, v]);
+        }
+        values
+    }
+
+    #[test]
+    fn samples_depend_only_on_pixel_index_and_seed() {
+        for kind in KINDS {
+            let mut first = kind.build(16, 7);
+            let expected = dimensions(first.as_mut(), 3, 5, 9);
+            assert!(expected.iter().all(|v| (0.0..1.0).contains(v)), "{:?} left [0, 1)", kind);
+
+            // other pixels and samples in between, or a fresh sampler, change nothing
+            dimensions(first.as_mut(), 4, 5, 9);
+            dimensions(first.as_mut(), 3, 5, 10);
+            assert_eq!(dimensions(first.as_mut(), 3, 5, 9), expected, "{:?} revisited", kind);
+            let mut second = kind.build(16, 7);
+            dimensions(second.as_mut(), 0, 0, 0);
+            assert_eq!(dimensions(second.as_mut(), 3, 5, 9), expected, "{:?} rebuilt", kind);
+
+            let mut reseeded = kind.build(16, 8);
+            assert_ne!(dimensions(reseeded.as_mut(), 3, 5, 9), expected, "{:?} ignored the seed", kind);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::arch::x86_64::*;
+use std::ops::{Add, Div, Mul, Sub};
+use crate::float::Float;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+// SSE versions of the hot vector and bounding box operations. They do the same
+// arithmetic in the same order as the scalar code, only several lanes at a time, so
+// renders do not change when the `simd` feature is turned on. SSE and SSE2 are part
+// of every x86_64 CPU, which makes the intrinsics below safe to call.
+
+// Four lanes, either the x, y and z of a vector with the fourth unused or one value
+// of each of four rays. A single register with f32.
+#[cfg(feature = "f32")]
+#[derive(Clone, Copy)]
+pub struct Float4(__m128);
+
+// SSE2 holds two f64, so x and y or the first two rays go in one register and the
+// rest in another.
+#[cfg(not(feature = "f32"))]
+#[derive(Clone, Copy)]
+pub struct Float4(__m128d, __m128d);
+
+#[cfg(feature = "f32")]
+impl Float4 {
+    fn new(x: Float, y: Float, z: Float) -> Float4 {
+        unsafe { Float4(_mm_set_ps(0.0, z, y, x)) }
+    }
+
+    pub fn from_array(v: [Float; 4]) -> Float4 {
+        unsafe { Float4(_mm_loadu_ps(v.as_ptr())) }
+    }
+
+    pub fn splat(v: Float) -> Float4 {
+        unsafe { Float4(_mm_set1_ps(v)) }
+    }
+
+    pub fn to_array(self) -> [Float; 4] {
+        let mut out = [0.0; 4];
+        unsafe { _mm_storeu_ps(out.as_mut_ptr(), self.0) };
+        out
+    }
+
+    pub fn sqrt(self) -> Float4 {
+        unsafe { Float4(_mm_sqrt_ps(self.0)) }
+    }
+
+    fn yzx(self) -> Float4 {
+        unsafe { Float4(_mm_shuffle_ps::<0b11_00_10_01>(self.0, self.0)) }
+    }
+
+    fn zxy(self) -> Float4 {
+        unsafe { Float4(_mm_shuffle_ps::<0b11_01_00_10>(self.0, self.0)) }
+    }
+
+    // (x + y) + z of the first three lanes, the order of the scalar code.
+    fn sum3(self) -> Float {
+        unsafe {
+            let xy = _mm_add_ss(self.0, _mm_shuffle_ps::<0b01>(self.0, self.0));
+            _mm_cvtss_f32(_mm_add_ss(xy, _mm_movehl_ps(self.0, self.0)))
+        }
+    }
+
+    fn max3(self) -> Float {
+        unsafe {
+            let xy = _mm_max_ss(self.0, _mm_shuffle_ps::<0b01>(self.0, self.0));
+            _mm_cvtss_f32(_mm_max_ss(xy, _mm_movehl_ps(self.0, self.0)))
+        }
+    }
+
+    fn min3(self) -> Float {
+        unsafe {
+            let xy = _mm_min_ss(self.0, _mm_shuffle_ps::<0b01>(self.0, self.0));
+            _mm_cvtss_f32(_mm_min_ss(xy, _mm_movehl_ps(self.0, self.0)))
+        }
+    }
+
+    // Per lane `if self > other { self } else { other }`, a NaN in self gives other.
+    pub fn max(self, other: Float4) -> Float4 {
+        unsafe { Float4(_mm_max_ps(self.0, other.0)) }
+    }
+
+    pub fn min(self, other: Float4) -> Float4 {
+        unsafe { Float4(_mm_min_ps(self.0, other.0)) }
+    }
+
+    // Float4 of `a` where self is negative, of `b` elsewhere.
+    pub fn select_negative(self, a: Float4, b: Float4) -> Float4 {
+        unsafe {
+            let mask = _mm_cmplt_ps(self.0, _mm_setzero_ps());
+            Float4(_mm_or_ps(_mm_and_ps(mask, a.0), _mm_andnot_ps(mask, b.0)))
+        }
+    }
+}
+
+#[cfg(not(feature = "f32"))]
+impl Float4 {
+    fn new(x: Float, y: Float, z: Float) -> Float4 {
+        unsafe { Float4(_mm_set_pd(y, x), _mm_set_sd(z)) }
+    }
+
+    pub fn from_array(v: [Float; 4]) -> Float4 {
+        unsafe { Float4(_mm_loadu_pd(v.as_ptr()), _mm_loadu_pd(v[2..].as_ptr())) }
+    }
+
+    pub fn splat(v: Float) -> Float4 {
+        unsafe { Float4(_mm_set1_pd(v), _mm_set1_pd(v)) }
+    }
+
+    pub fn to_array(self) -> [Float; 4] {
+        let mut out = [0.0; 4];
+        unsafe {
+            _mm_storeu_pd(out.as_mut_ptr(), self.0);
+            _mm_storeu_pd(out[2..].as_mut_ptr(), self.1);
+        }
+        out
+    }
+
+    pub fn sqrt(self) -> Float4 {
+        unsafe { Float4(_mm_sqrt_pd(self.0), _mm_sqrt_pd(self.1)) }
+    }
+
+    fn yzx(self) -> Float4 {
+        unsafe { Float4(_mm_shuffle_pd::<0b01>(self.0, self.1), self.0) }
+    }
+
+    fn zxy(self) -> Float4 {
+        unsafe { Float4(_mm_unpacklo_pd(self.1, self.0), _mm_unpackhi_pd(self.0, self.0)) }
+    }
+
+    // (x + y) + z of the first three lanes, the order of the scalar code.
+    fn sum3(self) -> Float {
+        unsafe { _mm_cvtsd_f64(_mm_add_sd(_mm_add_sd(self.0, _mm_unpackhi_pd(self.0, self.0)), self.1)) }
+    }
+
+    fn max3(self) -> Float {
+        unsafe { _mm_cvtsd_f64(_mm_max_sd(_mm_max_sd(self.0, _mm_unpackhi_pd(self.0, self.0)), self.1)) }
+    }
+
+    fn min3(self) -> Float {
+        unsafe { _mm_cvtsd_f64(_mm_min_sd(_mm_min_sd(self.0, _mm_unpackhi_pd(self.0, self.0)), self.1)) }
+    }
+
+    // Per lane `if self > other { self } else { other }`, a NaN in self gives other.
+    pub fn max(self, other: Float4) -> Float4 {
+        unsafe { Float4(_mm_max_pd(self.0, other.0), _mm_max_pd(self.1, other.1)) }
+    }
+
+    pub fn min(self, other: Float4) -> Float4 {
+        unsafe { Float4(_mm_min_pd(self.0, other.0), _mm_min_pd(self.1, other.1)) }
+    }
+
+    // Float4 of `a` where self is negative, of `b` elsewhere.
+    pub fn select_negative(self, a: Float4, b: Float4) -> Float4 {
+        unsafe {
+            let select = |v: __m128d, a: __m128d, b: __m128d| {
+                let mask = _mm_cmplt_pd(v, _mm_setzero_pd());
+                _mm_or_pd(_mm_and_pd(mask, a), _mm_andnot_pd(mask, b))
+            };
+            Float4(select(self.0, a.0, b.0), select(self.1, a.1, b.1))
+        }
+    }
+}
+
+macro_rules! lanes_op {
+    ($trait:ident, $fn:ident, $f32:ident, $f64:ident) => {
+        impl $trait for Float4 {
+            type Output = Float4;
+
+            #[cfg(feature = "f32")]
+            fn $fn(self, rhs: Float4) -> Float4 {
+                unsafe { Float4($f32(self.0, rhs.0)) }
+            }
+
+            #[cfg(not(feature = "f32"))]
+            fn $fn(self, rhs: Float4) -> Float4 {
+                unsafe { Float4($f64(self.0, rhs.0), $f64(self.1, rhs.1)) }
+            }
+        }
+    };
+}
+
+lanes_op!(Add, add, _mm_add_ps, _mm_add_pd);
+lanes_op!(Sub, sub, _mm_sub_ps, _mm_sub_pd);
+lanes_op!(Mul, mul, _mm_mul_ps, _mm_mul_pd);
+lanes_op!(Div, div, _mm_div_ps, _mm_div_pd);
+
+impl From<Vec3> for Float4 {
+    fn from(v: Vec3) -> Float4 {
+        Float4::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Point3> for Float4 {
+    fn from(p: Point3) -> Float4 {
+        Float4::new(p.x, p.y, p.z)
+    }
+}
+
+impl From<Float4> for Vec3 {
+    fn from(lanes: Float4) -> Vec3 {
+        let [x, y, z, _] = lanes.to_array();
+        Vec3::new(x, y, z)
+    }
+}
+
+pub fn dot(a: Vec3, b: Vec3) -> Float {
+    (Float4::from(a) * Float4::from(b)).sum3()
+}
+
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    let (a, b) = (Float4::from(a), Float4::from(b));
+    (a.yzx() * b.zxy() - a.zxy() * b.yzx()).into()
+}
+
+pub fn normalize(v: Vec3) -> Vec3 {
+    let v = Float4::from(v);
+    (v / Float4::splat((v * v).sum3().sqrt())).into()
+}
+
+// Slab test of all three axes at once. Where the ray runs inside a slab's plane the
+// distances are NaN and leave the interval alone, as in the scalar test.
+pub fn slab_test(min: Point3, max: Point3, ray: &Ray, ray_t_min: Float, ray_t_max: Float) -> Option<(Float, Float)> {
+    let origin = Float4::from(ray.origin);
+    let inv_d = Float4::splat(1.0) / Float4::from(ray.direction);
+    let to_min = (Float4::from(min) - origin) * inv_d;
+    let to_max = (Float4::from(max) - origin) * inv_d;
+    let near = inv_d.select_negative(to_max, to_min);
+    let far = inv_d.select_negative(to_min, to_max);
+    let t0 = near.max(Float4::splat(ray_t_min)).max3();
+    let t1 = far.min(Float4::splat(ray_t_max)).min3();
+    if t1 <= t0 {
+        return None;
+    }
+    Some((t0, t1))
+}
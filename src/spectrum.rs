@@ -0,0 +1,226 @@
+use std::ops::{Add, Div, Mul};
+use std::sync::OnceLock;
+use crate::color::Color;
+use crate::float::Float;
+
+// Wavelengths carried by every path, the first is the hero wavelength.
+pub const SAMPLES: usize = 4;
+
+// Visible range in nanometres that wavelengths are sampled from.
+const LAMBDA_MIN: Float = 360.0;
+const LAMBDA_MAX: Float = 830.0;
+
+// Linear sRGB from CIE XYZ, D65 white.
+const XYZ_TO_SRGB: [[Float; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+// Smits' reflectance spectra for RGB uplifting, at ten wavelengths spread evenly
+// from 380 to 720 nm.
+const SMITS_WHITE: [Float; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [Float; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [Float; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [Float; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [Float; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [Float; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [Float; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Values of a spectrum at the wavelengths of a path.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampledSpectrum(pub [Float; SAMPLES]);
+
+impl SampledSpectrum {
+    pub fn splat(value: Float) -> SampledSpectrum {
+        SampledSpectrum([value; SAMPLES])
+    }
+
+    pub fn max_value(&self) -> Float {
+        self.0.iter().fold(0.0, |max, v| max.max(*v))
+    }
+
+    // Uplifts an RGB reflectance or emission, Smits' method: the spectrum is white
+    // for the smallest component plus cyan, magenta or yellow and red, green or blue
+    // for the rest, so white stays flat and gray scales it.
+    pub fn from_rgb(rgb: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        let Color { r, g, b } = rgb;
+        let parts = if r <= g && r <= b {
+            if g <= b { [(r, &SMITS_WHITE), (g - r, &SMITS_CYAN), (b - g, &SMITS_BLUE)] }
+            else { [(r, &SMITS_WHITE), (b - r, &SMITS_CYAN), (g - b, &SMITS_GREEN)] }
+        } else if g <= r && g <= b {
+            if r <= b { [(g, &SMITS_WHITE), (r - g, &SMITS_MAGENTA), (b - r, &SMITS_BLUE)] }
+            else { [(g, &SMITS_WHITE), (b - g, &SMITS_MAGENTA), (r - b, &SMITS_RED)] }
+        } else if r <= g {
+            [(b, &SMITS_WHITE), (r - b, &SMITS_YELLOW), (g - r, &SMITS_GREEN)]
+        } else {
+            [(b, &SMITS_WHITE), (g - b, &SMITS_YELLOW), (r - g, &SMITS_RED)]
+        };
+        SampledSpectrum(wavelengths.lambda.map(|lambda| {
+            parts.iter().map(|(weight, table)| weight * smits(table, lambda)).sum()
+        }))
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum(std::array::from_fn(|i| self.0[i] * other.0[i]))
+    }
+}
+
+impl Div<Float> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn div(self, other: Float) -> SampledSpectrum {
+        SampledSpectrum(self.0.map(|v| v / other))
+    }
+}
+
+// Wavelengths in nanometres a path carries and their densities. The others are the
+// hero wavelength rotated evenly through the sampled range, so they share its path
+// until something disperses light.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledWavelengths {
+    pub lambda: [Float; SAMPLES],
+    pub pdf: [Float; SAMPLES],
+}
+
+impl SampledWavelengths {
+    // Samples more where the eye is more sensitive, with the density of PBRT's
+    // visible wavelength distribution.
+    pub fn sample_visible(u: Float) -> SampledWavelengths {
+        let lambda: [Float; SAMPLES] = std::array::from_fn(|i| {
+            let u = (u + i as Float / SAMPLES as Float).fract();
+            538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()
+        });
+        let pdf = lambda.map(|lambda| {
+            if (LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) { 0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2) } else { 0.0 }
+        });
+        SampledWavelengths { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> Float {
+        self.lambda[0]
+    }
+
+    // Leaves only the hero wavelength, for paths that split by wavelength. It now
+    // stands for all of them.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|pdf| *pdf == 0.0) {
+            return;
+        }
+        self.pdf[1..].fill(0.0);
+        self.pdf[0] /= SAMPLES as Float;
+    }
+
+    // Estimate of CIE XYZ from radiance at these wavelengths.
+    pub fn to_xyz(self, radiance: SampledSpectrum) -> [Float; 3] {
+        let mut xyz = [0.0; 3];
+        for i in 0..SAMPLES {
+            if self.pdf[i] > 0.0 {
+                let weight = radiance.0[i] / self.pdf[i];
+                for (sum, cmf) in xyz.iter_mut().zip(color_matching(self.lambda[i])) {
+                    *sum += cmf * weight;
+                }
+            }
+        }
+        xyz.map(|v| v / (SAMPLES as Float * y_integral()))
+    }
+
+    // Linear sRGB of radiance at these wavelengths, balanced so that a flat spectrum
+    // comes out white rather than the pink of equal energy under a D65 white.
+    pub fn to_rgb(self, radiance: SampledSpectrum) -> Color {
+        let rgb = xyz_to_srgb(self.to_xyz(radiance));
+        let white = white_balance();
+        Color::new(rgb.r / white.r, rgb.g / white.g, rgb.b / white.b)
+    }
+}
+
+pub fn xyz_to_srgb(xyz: [Float; 3]) -> Color {
+    let row = |r: [Float; 3]| r[0] * xyz[0] + r[1] * xyz[1] + r[2] * xyz[2];
+    Color::new(row(XYZ_TO_SRGB[0]), row(XYZ_TO_SRGB[1]), row(XYZ_TO_SRGB[2]))
+}
+
+// CIE 1931 colour matching functions at `lambda` nanometres, the multi-lobe
+// Gaussian fit of Wyman, Sloan and Shirley.
+pub fn color_matching(lambda: Float) -> [Float; 3] {
+    let g = |mu: Float, below: Float, above: Float| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    ]
+}
+
+// Integral of the y colour matching function over the sampled range, the Y of a
+// flat spectrum of one.
+fn y_integral() -> Float {
+    flat_xyz()[1]
+}
+
+// XYZ integrals of a flat spectrum of one over the sampled range, in 1 nm steps.
+fn flat_xyz() -> [Float; 3] {
+    static FLAT: OnceLock<[Float; 3]> = OnceLock::new();
+    *FLAT.get_or_init(|| {
+        (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).fold([0.0; 3], |sum, lambda| {
+            let cmf = color_matching(lambda as Float);
+            [sum[0] + cmf[0], sum[1] + cmf[1], sum[2] + cmf[2]]
+        })
+    })
+}
+
+fn white_balance() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| xyz_to_srgb(flat_xyz().map(|v| v / y_integral())))
+}
+
+// Linear interpolation into one of Smits' tables, constant past its ends.
+fn smits(table: &[Float; 10], lambda: Float) -> Float {
+    let position = ((lambda - 380.0) / (340.0 / 9.0)).clamp(0.0, 9.0);
+    let index = (position as usize).min(8);
+    let t = position - index as Float;
+    table[index] * (1.0 - t) + table[index + 1] * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_spectrum_is_white() {
+        // averaged over stratified hero wavelengths, as over the samples of a pixel
+        let count = 1000;
+        let sum = (0..count).fold(Color::BLACK, |sum, i| {
+            let wavelengths = SampledWavelengths::sample_visible((i as Float + 0.5) / count as Float);
+            sum + wavelengths.to_rgb(SampledSpectrum::splat(1.0))
+        });
+        let white = sum / count as Float;
+        for channel in [white.r, white.g, white.b] {
+            assert!((channel - 1.0).abs() < 0.01, "{:?}", white);
+        }
+    }
+
+    #[test]
+    fn gray_uplifts_to_a_flat_spectrum() {
+        for u in [0.0, 0.3, 0.7] {
+            let wavelengths = SampledWavelengths::sample_visible(u);
+            let spectrum = SampledSpectrum::from_rgb(Color::gray(0.4), &wavelengths);
+            for value in spectrum.0 {
+                assert!((value - 0.4).abs() < 1e-3, "{:?}", spectrum);
+            }
+        }
+    }
+}
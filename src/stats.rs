@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TOTAL: Mutex<Stats> = Mutex::new(Stats::new());
+
+thread_local! {
+    static LOCAL: RefCell<Stats> = const { RefCell::new(Stats::new()) };
+}
+
+// Counters of the work a render did. Every thread counts into its own copy, which
+// `flush` adds to the shared total, so counting costs no locking.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub primary_rays: u64,
+    pub secondary_rays: u64,
+    // visibility tests between two points, integrators without light sampling trace none
+    pub shadow_rays: u64,
+    pub sphere_tests: u64,
+    pub volume_tests: u64,
+    pub bvh_nodes_visited: u64,
+    pub paths: u64,
+    // rays traced by all paths together
+    pub path_rays: u64,
+    pub russian_roulette_kills: u64,
+    pub max_bounce_kills: u64,
+}
+
+impl Stats {
+    pub const fn new() -> Stats {
+        Stats {
+            primary_rays: 0,
+            secondary_rays: 0,
+            shadow_rays: 0,
+            sphere_tests: 0,
+            volume_tests: 0,
+            bvh_nodes_visited: 0,
+            paths: 0,
+            path_rays: 0,
+            russian_roulette_kills: 0,
+            max_bounce_kills: 0,
+        }
+    }
+
+    fn add(&mut self, other: &Stats) {
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.sphere_tests += other.sphere_tests;
+        self.volume_tests += other.volume_tests;
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+        self.paths += other.paths;
+        self.path_rays += other.path_rays;
+        self.russian_roulette_kills += other.russian_roulette_kills;
+        self.max_bounce_kills += other.max_bounce_kills;
+    }
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Updates the counters of this thread, nearly free while statistics are off.
+pub fn record(update: impl FnOnce(&mut Stats)) {
+    if ENABLED.load(Ordering::Relaxed) {
+        LOCAL.with(|local| update(&mut local.borrow_mut()));
+    }
+}
+
+// Hands the counts of this thread to the total, worker threads call this before
+// they finish.
+pub fn flush() {
+    let local = LOCAL.with(|local| std::mem::take(&mut *local.borrow_mut()));
+    TOTAL.lock().unwrap().add(&local);
+}
+
+// Counts of this thread not flushed yet.
+pub fn local() -> Stats {
+    LOCAL.with(|local| *local.borrow())
+}
+
+// Everything counted so far, starting over from zero.
+pub fn take() -> Stats {
+    flush();
+    std::mem::take(&mut *TOTAL.lock().unwrap())
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let per = |count: u64, per: u64| if per > 0 { count as f64 / per as f64 } else { 0.0 };
+        let rays = self.primary_rays + self.secondary_rays + self.shadow_rays;
+        let share = |count: u64| 100.0 * per(count, self.paths);
+        writeln!(f, "Render statistics")?;
+        writeln!(f, "  primary rays              {:>14}", self.primary_rays)?;
+        writeln!(f, "  secondary rays            {:>14}", self.secondary_rays)?;
+        writeln!(f, "  shadow rays               {:>14}", self.shadow_rays)?;
+        writeln!(f, "  sphere tests              {:>14} {:8.2} per ray", self.sphere_tests, per(self.sphere_tests, rays))?;
+        writeln!(f, "  volume tests              {:>14} {:8.2} per ray", self.volume_tests, per(self.volume_tests, rays))?;
+        writeln!(f, "  BVH nodes visited         {:>14} {:8.2} per ray", self.bvh_nodes_visited, per(self.bvh_nodes_visited, rays))?;
+        writeln!(f, "  paths                     {:>14}", self.paths)?;
+        writeln!(f, "  average path length       {:>14.2} rays", per(self.path_rays, self.paths))?;
+        writeln!(f, "  ended by Russian roulette {:>14} {:7.1}%", self.russian_roulette_kills, share(self.russian_roulette_kills))?;
+        writeln!(f, "  ended at max bounces      {:>14} {:7.1}%", self.max_bounce_kills, share(self.max_bounce_kills))
+    }
+}
@@ -0,0 +1,25 @@
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Path in the temp directory that no other test, nor another run of the tests,
+// writes to. Whatever ends up there is removed when it goes out of scope.
+pub struct TestFile(String);
+
+impl TestFile {
+    pub fn new(extension: &str) -> TestFile {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("rusttracer-{}-{}.{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed), extension);
+        TestFile(std::env::temp_dir().join(name).to_str().unwrap().to_string())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        // tests that fail early may never have written it
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::aabb::Aabb;
+use crate::animation::{Interpolation, Track};
+use crate::float::Float;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vec3::{Normal3, Point3, Vec3};
+
+const BOUNDS_SAMPLES_PER_KEY: usize = 16;
+
+// Pose of an object at `time` seconds: scaled, then rotated by `rotation` degrees
+// about x, y and z in that order, then translated.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: Float,
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: Float,
+}
+
+impl Keyframe {
+    pub fn new(time: Float, translation: Vec3, rotation: Vec3, scale: Float) -> Keyframe {
+        Keyframe { time, translation, rotation, scale }
+    }
+}
+
+struct Pose {
+    translation: Vec3,
+    // (sin, cos) of the rotation about each axis
+    angles: [(Float, Float); 3],
+    scale: Float,
+}
+
+impl Pose {
+    fn new(translation: Vec3, rotation: Vec3, scale: Float) -> Pose {
+        let angles = [rotation.x, rotation.y, rotation.z].map(|degrees| degrees.to_radians().sin_cos());
+        Pose { translation, angles, scale }
+    }
+
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        let [(sx, cx), (sy, cy), (sz, cz)] = self.angles;
+        let v = Vec3::new(v.x, cx * v.y - sx * v.z, sx * v.y + cx * v.z);
+        let v = Vec3::new(cy * v.x + sy * v.z, v.y, -sy * v.x + cy * v.z);
+        Vec3::new(cz * v.x - sz * v.y, sz * v.x + cz * v.y, v.z)
+    }
+
+    fn unrotate(&self, v: Vec3) -> Vec3 {
+        let [(sx, cx), (sy, cy), (sz, cz)] = self.angles;
+        let v = Vec3::new(cz * v.x + sz * v.y, -sz * v.x + cz * v.y, v.z);
+        let v = Vec3::new(cy * v.x - sy * v.z, v.y, sy * v.x + cy * v.z);
+        Vec3::new(v.x, cx * v.y + sx * v.z, -sx * v.y + cx * v.z)
+    }
+
+    fn point_to_world(&self, p: Point3) -> Point3 {
+        Point3::ORIGIN + self.rotate(self.scale * p.to_vec()) + self.translation
+    }
+
+    fn point_to_local(&self, p: Point3) -> Point3 {
+        Point3::ORIGIN + self.unrotate((p - self.translation).to_vec()) / self.scale
+    }
+
+    // The scale is uniform, so normals only turn with the rotation.
+    fn normal_to_world(&self, n: Normal3) -> Normal3 {
+        Normal3::from_vec(self.rotate(n.to_vec()))
+    }
+}
+
+// Moves an object along animation tracks for its translation, rotation and scale.
+pub struct KeyframedTransform {
+    translation: Track<Vec3>,
+    rotation: Track<Vec3>,
+    scale: Track<Float>,
+    object: Box<dyn Hittable>,
+}
+
+impl KeyframedTransform {
+    // Interpolates linearly between the keyframes.
+    pub fn new(object: Box<dyn Hittable>, keyframes: Vec<Keyframe>) -> KeyframedTransform {
+        assert!(!keyframes.is_empty(), "a keyframed transform needs at least one keyframe");
+        let linear = Interpolation::Linear;
+        let translation = keyframes.iter().fold(Track::new(), |t, k| t.key(k.time, k.translation, linear));
+        let rotation = keyframes.iter().fold(Track::new(), |t, k| t.key(k.time, k.rotation, linear));
+        let scale = keyframes.iter().fold(Track::new(), |t, k| t.key(k.time, k.scale, linear));
+        KeyframedTransform::from_tracks(object, translation, rotation, scale)
+    }
+
+    pub fn from_tracks(object: Box<dyn Hittable>, translation: Track<Vec3>, rotation: Track<Vec3>, scale: Track<Float>) -> KeyframedTransform {
+        KeyframedTransform { translation, rotation, scale, object }
+    }
+
+    fn pose_at(&self, time: Float) -> Pose {
+        Pose::new(self.translation.sample(time), self.rotation.sample(time), self.scale.sample(time))
+    }
+}
+
+impl Hittable for KeyframedTransform {
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool {
+        let pose = self.pose_at(ray.time);
+        // origin and direction map the same way, so t means the same in both spaces
+        let local = Ray::new(
+            pose.point_to_local(ray.origin),
+            pose.unrotate(ray.direction) / pose.scale,
+            ray.time,
+        );
+        if !self.object.hit(&local, ray_t_min, ray_t_max, hit_record) {
+            return false;
+        }
+        hit_record.point = ray.at(hit_record.t);
+        hit_record.normal = pose.normal_to_world(hit_record.normal);
+        true
+    }
+
+    // Curves can overshoot their keys, so the pose is sampled densely over the keyed
+    // time range and the box grows by the largest step between two samples.
+    fn bounding_box(&self) -> Aabb {
+        let corners = self.object.bounding_box().corners();
+        let reach = corners.iter().map(|c| c.to_vec().length()).fold(0.0, Float::max);
+        let rotates = !self.rotation.is_constant();
+        let times: Vec<Float> = self.translation.times().chain(self.rotation.times()).chain(self.scale.times()).collect();
+        let start = times.iter().copied().fold(Float::INFINITY, Float::min);
+        let end = times.iter().copied().fold(Float::NEG_INFINITY, Float::max);
+        let steps = BOUNDS_SAMPLES_PER_KEY * times.len();
+
+        let mut bounds: Option<Aabb> = None;
+        let mut largest_step: Float = 0.0;
+        let mut previous: Option<Pose> = None;
+        for step in 0..=steps {
+            let time = start + (end - start) * step as Float / steps as Float;
+            let pose = self.pose_at(time);
+            let posed = if rotates {
+                // rotating corners sweep arcs, bound them by the sphere around the pivot
+                let r = pose.scale * reach;
+                let pivot = Point3::ORIGIN + pose.translation;
+                Aabb::new(pivot - Vec3::new(r, r, r), pivot + Vec3::new(r, r, r))
+            } else {
+                let points = corners.map(|c| pose.point_to_world(c));
+                points[1..].iter().fold(Aabb::new(points[0], points[0]), |b, p| b.union(&Aabb::new(*p, *p)))
+            };
+            if let Some(previous) = previous {
+                let moved = (pose.translation - previous.translation).length() + (pose.scale - previous.scale).abs() * reach;
+                largest_step = largest_step.max(moved);
+            }
+            bounds = Some(bounds.map_or(posed, |b| b.union(&posed)));
+            previous = Some(pose);
+        }
+
+        let bounds = bounds.unwrap();
+        let pad = Vec3::new(largest_step, largest_step, largest_step);
+        Aabb::new(bounds.min - pad, bounds.max + pad)
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.object.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+
+    // Points spread over `transform`'s ball along a spiral, at times over the shutter.
+    fn assert_bounds_contain(transform: &KeyframedTransform, center: Point3, radius: Float) {
+        let bounds = transform.bounding_box();
+        for step in 0..=200 {
+            let pose = transform.pose_at(step as Float / 200.0);
+            for i in 0..64 {
+                let y = 1.0 - (i as Float + 0.5) / 32.0;
+                let angle = i as Float * 2.4;
+                let ring = (1.0 - y * y).sqrt();
+                let p = pose.point_to_world(center + radius * Vec3::new(ring * angle.cos(), y, ring * angle.sin()));
+                assert!(p.x >= bounds.min.x && p.y >= bounds.min.y && p.z >= bounds.min.z, "{:?} below {:?}", p, bounds.min);
+                assert!(p.x <= bounds.max.x && p.y <= bounds.max.y && p.z <= bounds.max.z, "{:?} above {:?}", p, bounds.max);
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_contain_the_object_throughout_the_shutter() {
+        // Catmull-Rom curves swing past the keys, the box has to catch that too
+        let curve = Interpolation::CatmullRom;
+        let translation = Track::new().key(0.0, Vec3::new(0.0, 0.0, 0.0), curve).key(0.2, Vec3::new(2.0, 1.0, 0.0), curve).key(1.0, Vec3::new(2.5, 1.0, -1.0), curve);
+        let scale = Track::new().key(0.0, 1.0, curve).key(0.5, 2.0, curve).key(1.0, 0.5, curve);
+        let still = Track::new().key(0.0, Vec3::new(0.0, 0.0, 0.0), curve);
+        let turning = Track::new().key(0.0, Vec3::new(0.0, 0.0, 0.0), curve).key(1.0, Vec3::new(30.0, 90.0, 10.0), curve);
+        // off the pivot, so the rotation swings it around
+        let center = Point3::new(1.0, 0.0, 0.0);
+        for rotation in [still, turning] {
+            let ball = Sphere::new(center, 0.5, None);
+            let transform = KeyframedTransform::from_tracks(Box::new(ball), translation.clone(), rotation, scale.clone());
+            assert_bounds_contain(&transform, center, 0.5);
+        }
+    }
+}
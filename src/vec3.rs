@@ -1,16 +1,35 @@
-use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::ops::{Add, Div, Index, Mul, Neg, Sub};
+use crate::float::Float;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+use crate::simd;
 
-#[derive(Clone, Copy, Debug, Default)]
+// Direction or offset in space.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Vec3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
-pub type Point3 = Vec3;
+// Position in space. Points move by vectors and their difference is one, adding two
+// points means nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point3 {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+// Surface normal of unit length. Transforms treat it apart from other directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Normal3 {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
 
 impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Vec3 {
         Vec3 {
             x,
             y,
@@ -18,46 +37,65 @@ impl Vec3 {
         }
     }
 
-    pub fn length_squared(self) -> f64 {
+    pub fn length_squared(self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn length(self) -> f64 {
+    pub fn length(self) -> Float {
         self.length_squared().sqrt()
     }
 
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
     pub fn normalize(self) -> Vec3 {
         self / self.length()
     }
 
-    pub fn dot(self, other: &Vec3) -> f64 {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    pub fn normalize(self) -> Vec3 {
+        simd::normalize(self)
+    }
+
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    pub fn dot(self, other: &Vec3) -> Float {
         self.x * other.x
             + self.y * other.y
             + self.z * other.z
     }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    pub fn dot(self, other: &Vec3) -> Float {
+        simd::dot(self, *other)
+    }
+
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
     pub fn cross(self, other: &Vec3) -> Vec3 {
-        let x = self.y * other.z - self.z - other.y;
-        let y = self.z * other.x - self.x - other.z;
-        let z = self.x * other.y - self.y - other.x;
+        let x = self.y * other.z - self.z * other.y;
+        let y = self.z * other.x - self.x * other.z;
+        let z = self.x * other.y - self.y * other.x;
         Vec3::new(x, y, z)
     }
 
-    pub fn reflect(self, normal: &Vec3) -> Vec3 {
-        self - 2.0 * *normal * self.dot(&normal)
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    pub fn cross(self, other: &Vec3) -> Vec3 {
+        simd::cross(self, *other)
+    }
+
+    pub fn reflect(self, normal: &Normal3) -> Vec3 {
+        self - 2.0 * *normal * normal.dot(&self)
     }
 
     pub fn near_zero(self) -> bool {
-        self.x.abs() < f64::EPSILON && self.y.abs() < f64::EPSILON && self.z.abs() < f64::EPSILON
+        self.x.abs() < Float::EPSILON && self.y.abs() < Float::EPSILON && self.z.abs() < Float::EPSILON
     }
 
-    pub fn refract(self, n: &Vec3, etai_over_etat: f64) -> Vec3 {
-        let cos_theta = -self.dot(n).min(1.0);
+    pub fn refract(self, n: &Normal3, etai_over_etat: Float) -> Vec3 {
+        let cos_theta = -n.dot(&self).min(1.0);
         let r_out_prep = etai_over_etat * (self + cos_theta * *n);
         let r_out_parallel = -(1.0 - r_out_prep.length_squared()).abs().sqrt();
         r_out_prep  + r_out_parallel * *n
      }
 
-    fn rotate_y(&self, theta: f64) -> Vec3 {
+    fn rotate_y(&self, theta: Float) -> Vec3 {
         let cos_theta = theta.cos();
         let sin_theta = theta.sin();
 
@@ -68,7 +106,7 @@ impl Vec3 {
         }
     }
 
-    fn rotate_x(&self, phi: f64) -> Vec3 {
+    fn rotate_x(&self, phi: Float) -> Vec3 {
         let cos_phi = phi.cos();
         let sin_phi = phi.sin();
 
@@ -79,7 +117,7 @@ impl Vec3 {
         }
     }
 
-    pub fn rotate(&self, theta: f64, phi: f64) -> Vec3 {
+    pub fn rotate(&self, theta: Float, phi: Float) -> Vec3 {
         self.rotate_x(theta).rotate_y(phi)
     }
 }
@@ -100,10 +138,10 @@ impl Sub for Vec3 {
     }
 }
 
-impl Mul<f64> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Self;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         Self {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -112,22 +150,10 @@ impl Mul<f64> for Vec3 {
     }
 }
 
-impl Mul for Vec3 {
+impl Div<Float> for Vec3 {
     type Output = Self;
 
-    fn mul(self, rhs: Vec3) -> Self::Output {
-        Self {
-            x: self.x * rhs.x,
-            y: self.y * rhs.y,
-            z: self.z * rhs.z,
-        }
-    }
-}
-
-impl Div<f64> for Vec3 {
-    type Output = Self;
-
-    fn div(self, rhs: f64) -> Self::Output {
+    fn div(self, rhs: Float) -> Self::Output {
         Self {
             x: self.x / rhs,
             y: self.y / rhs,
@@ -136,7 +162,7 @@ impl Div<f64> for Vec3 {
     }
 }
 
-impl Mul<Vec3> for f64 {
+impl Mul<Vec3> for Float {
     type Output = Vec3;
 
     fn mul(self, rhs: Vec3) -> Self::Output {
@@ -151,4 +177,148 @@ impl Neg for Vec3 {
     fn neg(self) -> Self::Output {
         Vec3::new(-self.x, -self.y, -self.z)
     }
-}
\ No newline at end of file
+}
+
+impl Index<usize> for Vec3 {
+    type Output = Float;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z,
+        }
+    }
+}
+
+impl Point3 {
+    pub const ORIGIN: Point3 = Point3 { x: 0.0, y: 0.0, z: 0.0 };
+
+    pub fn new(x: Float, y: Float, z: Float) -> Point3 {
+        Point3 { x, y, z }
+    }
+
+    // Componentwise minimum and maximum, the corners of the box around both.
+    pub fn min(self, other: Point3) -> Point3 {
+        Point3::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    pub fn max(self, other: Point3) -> Point3 {
+        Point3::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    // Offset from the origin.
+    pub fn to_vec(self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+}
+
+impl Add<Vec3> for Point3 {
+    type Output = Point3;
+
+    fn add(self, rhs: Vec3) -> Point3 {
+        Point3::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub<Vec3> for Point3 {
+    type Output = Point3;
+
+    fn sub(self, rhs: Vec3) -> Point3 {
+        Point3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Sub for Point3 {
+    type Output = Vec3;
+
+    fn sub(self, rhs: Point3) -> Vec3 {
+        Vec3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Index<usize> for Point3 {
+    type Output = Float;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z,
+        }
+    }
+}
+
+impl Normal3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Normal3 {
+        Normal3 { x, y, z }
+    }
+
+    // Normal along `v`, which already has unit length.
+    pub fn from_vec(v: Vec3) -> Normal3 {
+        debug_assert!((v.length_squared() - 1.0).abs() < 1e-3, "normal of length {}", v.length());
+        Normal3::new(v.x, v.y, v.z)
+    }
+
+    pub fn dot(self, other: &Vec3) -> Float {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn to_vec(self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+}
+
+impl Neg for Normal3 {
+    type Output = Normal3;
+
+    fn neg(self) -> Normal3 {
+        Normal3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Add<Vec3> for Normal3 {
+    type Output = Vec3;
+
+    fn add(self, rhs: Vec3) -> Vec3 {
+        self.to_vec() + rhs
+    }
+}
+
+impl Mul<Float> for Normal3 {
+    type Output = Vec3;
+
+    fn mul(self, rhs: Float) -> Vec3 {
+        self.to_vec() * rhs
+    }
+}
+
+impl Mul<Normal3> for Float {
+    type Output = Vec3;
+
+    fn mul(self, rhs: Normal3) -> Vec3 {
+        rhs * self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cross_is_right_handed_and_perpendicular() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        assert_eq!(x.cross(&y), z);
+        assert_eq!(y.cross(&z), x);
+        assert_eq!(z.cross(&x), y);
+
+        let a = Vec3::new(0.3, -1.2, 2.5);
+        let b = Vec3::new(-0.7, 0.4, 1.1);
+        let c = a.cross(&b);
+        assert!(c.dot(&a).abs() < 1e-5);
+        assert!(c.dot(&b).abs() < 1e-5);
+        assert_eq!(b.cross(&a), -c);
+    }
+}
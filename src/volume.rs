@@ -0,0 +1,442 @@
+use std::fs;
+use std::io;
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::float::{to_f32, to_f64, Float};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::{mix_hash, Pcg32};
+use crate::stats;
+use crate::vec3::{Normal3, Point3, Vec3};
+
+const MAJORANT_RESOLUTION: usize = 16;
+
+// Scalar voxel grid spanning the unit cube, voxel centers sit at (i + 0.5) / n.
+pub struct DensityGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub data: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn from_fn(nx: usize, ny: usize, nz: usize, f: impl Fn(Point3) -> Float) -> DensityGrid {
+        let mut data = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let p = Point3::new(
+                        (x as Float + 0.5) / nx as Float,
+                        (y as Float + 0.5) / ny as Float,
+                        (z as Float + 0.5) / nz as Float,
+                    );
+                    data.push(to_f32(f(p)));
+                }
+            }
+        }
+        DensityGrid { nx, ny, nz, data }
+    }
+
+    // Grid files start with a text line `GRID nx ny nz`, followed by nx * ny * nz
+    // little endian f32 values with x varying fastest, then y, then z.
+    pub fn load(path: &str) -> io::Result<DensityGrid> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+        let bytes = fs::read(path)?;
+        let header_end = bytes.iter().position(|b| *b == b'\n').ok_or_else(|| invalid("missing header"))?;
+        let header = std::str::from_utf8(&bytes[..header_end]).map_err(|_| invalid("header is not text"))?;
+
+        let mut parts = header.split_whitespace();
+        if parts.next() != Some("GRID") {
+            return Err(invalid("not a grid file"));
+        }
+        let dims = parts
+            .map(|p| p.parse::<usize>().map_err(|_| invalid("bad resolution")))
+            .collect::<io::Result<Vec<usize>>>()?;
+        if dims.len() != 3 || dims.contains(&0) {
+            return Err(invalid("expected three non-zero dimensions"));
+        }
+
+        let count = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]))
+            .ok_or_else(|| invalid("bad resolution"))?;
+        let body = &bytes[header_end + 1..];
+        if body.len() / 4 < count {
+            return Err(invalid("file is truncated"));
+        }
+        let data = body
+            .chunks_exact(4)
+            .take(count)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+
+        Ok(DensityGrid { nx: dims[0], ny: dims[1], nz: dims[2], data })
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> Float {
+        self.data[(z * self.ny + y) * self.nx + x] as Float
+    }
+
+    // Trilinear lookup, p is in grid space [0, 1]^3.
+    pub fn sample(&self, p: Point3) -> Float {
+        let (x0, x1, fx) = Self::axis_lerp(p.x, self.nx);
+        let (y0, y1, fy) = Self::axis_lerp(p.y, self.ny);
+        let (z0, z1, fz) = Self::axis_lerp(p.z, self.nz);
+
+        let lerp = |a: Float, b: Float, t: Float| a + (b - a) * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+
+    fn axis_lerp(p: Float, n: usize) -> (usize, usize, Float) {
+        let g = (p * n as Float - 0.5).clamp(0.0, (n - 1) as Float);
+        let i0 = g.floor() as usize;
+        let i1 = (i0 + 1).min(n - 1);
+        (i0, i1, g - i0 as Float)
+    }
+
+    // Largest voxel value that can influence the region [lo, hi] (grid space) of each axis.
+    fn max_in(&self, lo: Point3, hi: Point3) -> Float {
+        let range = |lo: Float, hi: Float, n: usize| {
+            let a = ((lo * n as Float - 0.5).floor().max(0.0) as usize).min(n - 1);
+            let b = ((hi * n as Float - 0.5).ceil().max(0.0) as usize).min(n - 1);
+            a..=b
+        };
+        let mut max: Float = 0.0;
+        for z in range(lo.z, hi.z, self.nz) {
+            for y in range(lo.y, hi.y, self.ny) {
+                for x in range(lo.x, hi.x, self.nx) {
+                    max = max.max(self.voxel(x, y, z));
+                }
+            }
+        }
+        max
+    }
+}
+
+// Coarse grid of upper bounds on the density, lets delta tracking take long steps
+// through thin regions instead of using one global maximum for the whole volume.
+struct MajorantGrid {
+    res: usize,
+    values: Vec<Float>,
+}
+
+impl MajorantGrid {
+    fn build(grid: &DensityGrid, res: usize, scale: Float) -> MajorantGrid {
+        let mut values = Vec::with_capacity(res * res * res);
+        let cell = 1.0 / res as Float;
+        for z in 0..res {
+            for y in 0..res {
+                for x in 0..res {
+                    let lo = Point3::new(x as Float * cell, y as Float * cell, z as Float * cell);
+                    let hi = lo + Vec3::new(cell, cell, cell);
+                    values.push(grid.max_in(lo, hi) * scale);
+                }
+            }
+        }
+        MajorantGrid { res, values }
+    }
+
+    fn get(&self, cell: [usize; 3]) -> Float {
+        self.values[(cell[2] * self.res + cell[1]) * self.res + cell[0]]
+    }
+}
+
+// Heterogeneous participating medium filling an axis aligned box. Free flight
+// distances are sampled with delta tracking against the majorant grid, a real
+// collision is reported as a hit with an isotropic phase function.
+pub struct Volume {
+    bounds: Aabb,
+    density: DensityGrid,
+    majorants: MajorantGrid,
+    density_scale: Float,
+    albedo: Color,
+    emission: Color,
+    temperature: Option<(DensityGrid, Float)>,
+}
+
+impl Volume {
+    pub fn new(bounds: Aabb, density: DensityGrid, density_scale: Float, albedo: Color) -> Volume {
+        let majorants = MajorantGrid::build(&density, MAJORANT_RESOLUTION, density_scale);
+        Volume {
+            bounds,
+            density,
+            majorants,
+            density_scale,
+            albedo,
+            emission: Color::BLACK,
+            temperature: None,
+        }
+    }
+
+    pub fn with_emission(mut self, emission: Color) -> Volume {
+        self.emission = emission;
+        self
+    }
+
+    // Temperature grid values are multiplied by `kelvin_scale` and emit blackbody radiation.
+    pub fn with_temperature(mut self, temperature: DensityGrid, kelvin_scale: Float) -> Volume {
+        self.temperature = Some((temperature, kelvin_scale));
+        self
+    }
+
+    // Rising plume of fbm noise with a hot core near its base.
+    pub fn procedural_smoke(bounds: Aabb, resolution: usize, seed: u32) -> Volume {
+        let plume = |p: Point3| {
+            let dx = p.x - 0.5;
+            let dz = p.z - 0.5;
+            let radius = 0.12 + 0.3 * p.y;
+            let falloff = (1.0 - (dx * dx + dz * dz).sqrt() / radius).max(0.0);
+            falloff * (1.0 - p.y).min(0.3) / 0.3
+        };
+        let density = DensityGrid::from_fn(resolution, resolution, resolution, |p| {
+            let noise = fbm(Point3::ORIGIN + p.to_vec() * 6.0, seed, 5);
+            (noise * 2.0 - 0.6).max(0.0) * plume(p)
+        });
+        // the fire is coarser than the smoke, but never down to no voxels at all
+        let coarse = (resolution / 2).max(1);
+        let temperature = DensityGrid::from_fn(coarse, coarse, coarse, |p| {
+            (plume(p) * (1.0 - 2.5 * p.y)).max(0.0)
+        });
+
+        Volume::new(bounds, density, 25.0, Color::gray(0.7))
+            .with_temperature(temperature, 2200.0)
+    }
+
+    fn to_grid(&self, p: Point3) -> Point3 {
+        let rel = p - self.bounds.min;
+        let size = self.bounds.size();
+        Point3::new(rel.x / size.x, rel.y / size.y, rel.z / size.z)
+    }
+
+    fn emitted(&self, grid_p: Point3) -> Color {
+        let mut radiance = self.emission;
+        if let Some((temperature, kelvin_scale)) = &self.temperature {
+            let kelvin = temperature.sample(grid_p) * kelvin_scale;
+            if kelvin > 500.0 {
+                // Stefan-Boltzmann falloff relative to a 1500K flame
+                radiance = radiance + blackbody(kelvin) * (kelvin / 1500.0).powi(4);
+            }
+        }
+        // only the absorbed fraction of a collision emits
+        (Color::WHITE - self.albedo) * radiance
+    }
+}
+
+impl Hittable for Volume {
+    fn hit(&self, ray: &Ray, ray_t_min: Float, ray_t_max: Float, hit_record: &mut HitRecord) -> bool {
+        stats::record(|s| s.volume_tests += 1);
+        let (t_enter, t_exit) = match self.bounds.hit(ray, ray_t_min, ray_t_max) {
+            Some(interval) => interval,
+            None => return false,
+        };
+
+        let ray_length = ray.direction.length();
+        // seeded by the ray itself, so a render does not depend on which thread or
+        // run traced it
+        let seed = [ray.origin.x, ray.origin.y, ray.origin.z, ray.direction.x, ray.direction.y, ray.direction.z, ray.time]
+            .iter()
+            .fold(0, |h, v| mix_hash(h ^ to_f64(*v).to_bits()));
+        let mut rand = Pcg32::new(seed);
+        let mut cells = MajorantWalk::new(self, ray, t_enter, t_exit);
+
+        while let Some((cell_t_min, cell_t_max, majorant)) = cells.next_cell() {
+            if majorant <= 0.0 {
+                continue;
+            }
+            let mut t = cell_t_min;
+            loop {
+                let u = rand.next_float();
+                t -= (1.0 - u).ln() / (majorant * ray_length);
+                if t >= cell_t_max {
+                    break;
+                }
+                let grid_p = self.to_grid(ray.at(t));
+                let density = self.density.sample(grid_p) * self.density_scale;
+                if rand.next_float() * majorant < density {
+                    hit_record.t = t;
+                    hit_record.point = ray.at(t);
+                    hit_record.normal = Normal3::from_vec(-ray.direction / ray_length);
+                    hit_record.front_face = true;
+                    hit_record.material = Some(Material::Isotropic {
+                        albedo: self.albedo,
+                        emission: self.emitted(grid_p),
+                    });
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+// 3D DDA over the majorant cells a ray passes through.
+struct MajorantWalk<'a> {
+    majorants: &'a MajorantGrid,
+    cell: [usize; 3],
+    step: [isize; 3],
+    t_next: [Float; 3],
+    t_delta: [Float; 3],
+    t: Float,
+    t_exit: Float,
+}
+
+impl<'a> MajorantWalk<'a> {
+    fn new(volume: &'a Volume, ray: &Ray, t_enter: Float, t_exit: Float) -> MajorantWalk<'a> {
+        let res = volume.majorants.res;
+        let size = volume.bounds.size();
+        let start = volume.to_grid(ray.at(t_enter));
+        let mut walk = MajorantWalk {
+            majorants: &volume.majorants,
+            cell: [0; 3],
+            step: [0; 3],
+            t_next: [Float::INFINITY; 3],
+            t_delta: [Float::INFINITY; 3],
+            t: t_enter,
+            t_exit,
+        };
+
+        for axis in 0..3 {
+            let cell = ((start[axis] * res as Float) as usize).min(res - 1);
+            walk.cell[axis] = cell;
+            let cell_size = size[axis] / res as Float;
+            let d = ray.direction[axis];
+            if d > 0.0 {
+                let boundary = volume.bounds.min[axis] + (cell + 1) as Float * cell_size;
+                walk.step[axis] = 1;
+                walk.t_next[axis] = (boundary - ray.origin[axis]) / d;
+                walk.t_delta[axis] = cell_size / d;
+            } else if d < 0.0 {
+                let boundary = volume.bounds.min[axis] + cell as Float * cell_size;
+                walk.step[axis] = -1;
+                walk.t_next[axis] = (boundary - ray.origin[axis]) / d;
+                walk.t_delta[axis] = -cell_size / d;
+            }
+        }
+        walk
+    }
+
+    fn next_cell(&mut self) -> Option<(Float, Float, Float)> {
+        if self.t >= self.t_exit {
+            return None;
+        }
+        let axis = if self.t_next[0] < self.t_next[1] {
+            if self.t_next[0] < self.t_next[2] { 0 } else { 2 }
+        } else if self.t_next[1] < self.t_next[2] { 1 } else { 2 };
+
+        let t_min = self.t;
+        let t_max = self.t_next[axis].min(self.t_exit);
+        let majorant = self.majorants.get(self.cell);
+
+        self.t = t_max;
+        self.t_next[axis] += self.t_delta[axis];
+        let next = self.cell[axis] as isize + self.step[axis];
+        if next < 0 || next >= self.majorants.res as isize {
+            self.t = self.t_exit;
+        } else {
+            self.cell[axis] = next as usize;
+        }
+
+        Some((t_min, t_max, majorant))
+    }
+}
+
+// Approximate sRGB color of a blackbody at the given temperature, normalized to [0, 1].
+pub fn blackbody(kelvin: Float) -> Color {
+    let t = (kelvin / 100.0).clamp(10.0, 400.0);
+    let r = if t <= 66.0 { 255.0 } else { 329.698727446 * (t - 60.0).powf(-0.1332047592) };
+    let g = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+    Color::new(r, g, b).clamp(0.0, 255.0) / 255.0
+}
+
+fn hash3(x: i64, y: i64, z: i64, seed: u32) -> Float {
+    let mut h = (x as u64).wrapping_mul(0x8da6b343)
+        ^ (y as u64).wrapping_mul(0xd8163841)
+        ^ (z as u64).wrapping_mul(0xcb1ab31f)
+        ^ (seed as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    (h >> 11) as Float / (1u64 << 53) as Float
+}
+
+fn value_noise(p: Point3, seed: u32) -> Float {
+    let (x0, y0, z0) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let smooth = |t: Float| t * t * (3.0 - 2.0 * t);
+    let (fx, fy, fz) = (smooth(p.x - x0), smooth(p.y - y0), smooth(p.z - z0));
+    let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+
+    let lerp = |a: Float, b: Float, t: Float| a + (b - a) * t;
+    let corner = |dx: i64, dy: i64, dz: i64| hash3(ix + dx, iy + dy, iz + dz, seed);
+    let c00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fx);
+    let c10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fx);
+    let c01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fx);
+    let c11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fx);
+    lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+}
+
+pub fn fbm(p: Point3, seed: u32, octaves: u32) -> Float {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0;
+    let mut norm = 0.0;
+    for octave in 0..octaves {
+        sum += amplitude * value_noise(Point3::ORIGIN + p.to_vec() * frequency, seed.wrapping_add(octave));
+        norm += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / norm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_file::TestFile;
+
+    #[test]
+    fn load_checks_the_resolution_against_the_file() {
+        let file = TestFile::new("grid");
+        let load = |header: &str, values: &[f32]| {
+            let mut bytes = format!("{}\n", header).into_bytes();
+            values.iter().for_each(|v| bytes.extend_from_slice(&v.to_le_bytes()));
+            fs::write(file.path(), bytes).unwrap();
+            DensityGrid::load(file.path())
+        };
+
+        let grid = load("GRID 2 1 1", &[0.25, 4.0]).unwrap();
+        assert_eq!((grid.nx, grid.ny, grid.nz, grid.data), (2, 1, 1, vec![0.25, 4.0]));
+        for header in ["GRID 2 2 1", "GRID 4294967296 4294967296 2", "GRID 18446744073709551615 2 1"] {
+            assert!(load(header, &[0.25, 4.0]).is_err_and(|e| e.kind() == io::ErrorKind::InvalidData), "{}", header);
+        }
+    }
+
+    #[test]
+    fn smoke_of_a_single_voxel_still_has_a_temperature() {
+        let bounds = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let smoke = Volume::procedural_smoke(bounds, 1, 3);
+        let (temperature, _) = smoke.temperature.as_ref().unwrap();
+        assert_eq!((temperature.nx, temperature.ny, temperature.nz), (1, 1, 1));
+        smoke.emitted(Point3::new(0.5, 0.1, 0.5));
+    }
+}